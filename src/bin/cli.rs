@@ -6,12 +6,15 @@ use discord_chatbot::{
     services::{
         chatgpt_service::{post_chat_completions, response_extract_stream},
         discord_service::{
-            delete_application_command, delete_guild_command, generate_chat_command,
-            generate_chata_command, generate_chats_command, get_application_commands,
-            get_get_channel, get_get_message, get_get_messages, get_guild_commands,
+            delete_application_command, delete_guild_command, generate_ask_command,
+            generate_chat_command, generate_chata_command, generate_chats_command,
+            generate_defaults_command, generate_imagine_command, generate_persona_command,
+            generate_prompt_command, generate_stop_command, generate_topic_check_command,
+            generate_transcribe_command, get_application_commands, get_get_channel,
+            get_get_message, get_get_messages, get_guild_commands,
             post_create_application_chat_command, post_create_application_message_command,
             post_create_guild_chat_command, post_create_guild_message_command,
-            post_followup_message,
+            post_followup_message, MessageCursor,
         },
     },
 };
@@ -98,6 +101,65 @@ pub async fn main() -> Result<(), Error> {
                     post_create_guild_chat_command(&client, &guild_id, &generate_chata_command())
                         .await?;
                 println!("(GUILD)chata command created: {:?}", response.text().await?);
+                let response =
+                    post_create_guild_chat_command(&client, &guild_id, &generate_ask_command())
+                        .await?;
+                println!("(GUILD)ask command created: {:?}", response.text().await?);
+                let response =
+                    post_create_guild_chat_command(&client, &guild_id, &generate_prompt_command())
+                        .await?;
+                println!(
+                    "(GUILD)prompt command created: {:?}",
+                    response.text().await?
+                );
+                let response =
+                    post_create_guild_chat_command(&client, &guild_id, &generate_imagine_command())
+                        .await?;
+                println!(
+                    "(GUILD)imagine command created: {:?}",
+                    response.text().await?
+                );
+                let response =
+                    post_create_guild_chat_command(&client, &guild_id, &generate_stop_command())
+                        .await?;
+                println!("(GUILD)stop command created: {:?}", response.text().await?);
+                let response = post_create_guild_chat_command(
+                    &client,
+                    &guild_id,
+                    &generate_transcribe_command(),
+                )
+                .await?;
+                println!(
+                    "(GUILD)transcribe command created: {:?}",
+                    response.text().await?
+                );
+                let response =
+                    post_create_guild_chat_command(&client, &guild_id, &generate_persona_command())
+                        .await?;
+                println!(
+                    "(GUILD)persona command created: {:?}",
+                    response.text().await?
+                );
+                let response = post_create_guild_chat_command(
+                    &client,
+                    &guild_id,
+                    &generate_topic_check_command(),
+                )
+                .await?;
+                println!(
+                    "(GUILD)topic-check command created: {:?}",
+                    response.text().await?
+                );
+                let response = post_create_guild_chat_command(
+                    &client,
+                    &guild_id,
+                    &generate_defaults_command(),
+                )
+                .await?;
+                println!(
+                    "(GUILD)defaults command created: {:?}",
+                    response.text().await?
+                );
                 let response = post_create_guild_message_command(&client, &guild_id).await?;
                 println!(
                     "(GUILD)message command created: {:?}",
@@ -116,6 +178,36 @@ pub async fn main() -> Result<(), Error> {
                     post_create_application_chat_command(&client, &generate_chata_command())
                         .await?;
                 println!("chata command created: {:?}", response.text().await?);
+                let response =
+                    post_create_application_chat_command(&client, &generate_ask_command()).await?;
+                println!("ask command created: {:?}", response.text().await?);
+                let response =
+                    post_create_application_chat_command(&client, &generate_prompt_command())
+                        .await?;
+                println!("prompt command created: {:?}", response.text().await?);
+                let response =
+                    post_create_application_chat_command(&client, &generate_imagine_command())
+                        .await?;
+                println!("imagine command created: {:?}", response.text().await?);
+                let response =
+                    post_create_application_chat_command(&client, &generate_stop_command()).await?;
+                println!("stop command created: {:?}", response.text().await?);
+                let response =
+                    post_create_application_chat_command(&client, &generate_transcribe_command())
+                        .await?;
+                println!("transcribe command created: {:?}", response.text().await?);
+                let response =
+                    post_create_application_chat_command(&client, &generate_persona_command())
+                        .await?;
+                println!("persona command created: {:?}", response.text().await?);
+                let response =
+                    post_create_application_chat_command(&client, &generate_topic_check_command())
+                        .await?;
+                println!("topic-check command created: {:?}", response.text().await?);
+                let response =
+                    post_create_application_chat_command(&client, &generate_defaults_command())
+                        .await?;
+                println!("defaults command created: {:?}", response.text().await?);
                 let response = post_create_application_message_command(&client).await?;
                 println!("message command created: {:?}", response.text().await?);
             }
@@ -154,7 +246,13 @@ pub async fn main() -> Result<(), Error> {
             limit,
         } => {
             info!("get channel messages: {channel_id}");
-            let response = get_get_messages(&client, &channel_id, before, Some(limit)).await?;
+            let response = get_get_messages(
+                &client,
+                &channel_id,
+                before.map(MessageCursor::Before),
+                Some(limit),
+            )
+            .await?;
             println!("{:?}", response.text().await?);
         }
         Action::GetMessage {
@@ -167,14 +265,8 @@ pub async fn main() -> Result<(), Error> {
         }
         Action::FollowUp { token } => {
             info!("follow up: {token}");
-            let response = post_followup_message(
-                &client,
-                &token,
-                &WebhookRequest {
-                    content: "Follow up!".to_string(),
-                },
-            )
-            .await?;
+            let response =
+                post_followup_message(&client, &token, &WebhookRequest::new("Follow up!")).await?;
             println!("{:?}", response.text().await?);
         }
         Action::Chat { text, stream } => {
@@ -196,7 +288,7 @@ pub async fn main() -> Result<(), Error> {
                 pin_mut!(stream); // needed for iteration
                 while let Some(value) = stream.next().await {
                     let value = value?;
-                    print!("{}", value);
+                    print!("{}", value.content);
                 }
             } else {
                 let response = response.json::<ChatCompletionResponse>().await?;
@@ -1,24 +1,281 @@
-use aws_lambda_events::event::{dynamodb::Event, streams::DynamoDbEventResponse};
+use aws_lambda_events::event::{
+    dynamodb::{Event, StreamRecord},
+    streams::DynamoDbEventResponse,
+};
+use base64::{engine::general_purpose::STANDARD, Engine};
 use discord_chatbot::{
+    constants::{MESSAGE_CONTENT_MAX_LENGTH, THREAD_NAME_MAX_LENGTH},
     models::{
-        chatgpt::chat_completion::ChatCompletionRequest,
-        discord::{message::Message, webhook_request::WebhookRequest},
-        dynamo::discord_command::{CommandType, DiscordCommand},
+        chatgpt::image_generation::{
+            ImageGenerationData, ImageGenerationRequest, ImageGenerationResponse,
+        },
+        discord::{attachment::Attachment, channel::Channel, webhook_request::WebhookRequest},
+        dynamo::discord_command::{
+            AskCommand, ChatCommand, ChatCommandMessage, CommandType, DiscordCommand,
+            ImagineCommand, TranscribeCommand,
+        },
     },
     service::ServiceFn,
     services::{
-        chatgpt_service::{post_chat_completions, response_extract_stream},
-        discord_service::{edit_followup_message, post_followup_message},
+        chatgpt_service::{generate_title, post_image_generations},
+        conversation_service::{
+            build_chat_request, convert_messsages_to_chat_command_message, PromptOptions,
+        },
+        discord_service::{
+            post_followup_message, post_followup_message_with_files, post_start_thread, UploadFile,
+        },
+        dynamo_service::set_command_thread,
+        history_service::read_requested_history,
+        reply_service::{stream_chat_completion, AnswerJob, ReplyTarget},
+        tool_service::{ToolContext, ToolRegistry},
+        transcription_service::{transcribe_attachment, transcriber_from_env},
     },
 };
-use futures_util::{pin_mut, StreamExt};
 use lambda_runtime::{run, Error, LambdaEvent};
-use std::sync::Arc;
+use std::{env, sync::Arc};
 use tokio::task::JoinSet;
 use tracing::{error, info, warn};
 
+// Attributes written by the Stop button and the worker while a command is answered
+const STATUS_ATTRIBUTES: [&str; 5] = ["Cancelled", "ThreadId", "Usage", "CompletedAt", "UpdatedAt"];
+
+/// Whether a MODIFY changed nothing but `STATUS_ATTRIBUTES`. Without the old image the
+/// change can't be told apart from a status update and is treated as one.
+fn is_status_update(change: &StreamRecord) -> bool {
+    if change.old_image.is_empty() {
+        return true;
+    }
+    !change
+        .old_image
+        .keys()
+        .chain(change.new_image.keys())
+        .filter(|name| !STATUS_ATTRIBUTES.contains(&name.as_str()))
+        .any(|name| change.old_image.get(name) != change.new_image.get(name))
+}
+
 struct Service {
     client: Arc<reqwest::Client>,
+    dynamo_client: Arc<aws_sdk_dynamodb::Client>,
+    table_name: String,
+    tools: Arc<ToolRegistry>,
+}
+
+async fn process_chat_command(
+    client: &reqwest::Client,
+    job: AnswerJob<'_>,
+    tools: &ToolRegistry,
+    mut chat_command: ChatCommand,
+) -> Result<(), Error> {
+    if let Some(history) = chat_command.history.take() {
+        let messages = read_requested_history(client, &chat_command.channel_id, &history).await?;
+        chat_command.source_message_ids = messages.iter().map(|m| m.id.clone()).collect();
+        let mut command_messages = convert_messsages_to_chat_command_message(messages);
+        command_messages.append(&mut chat_command.messages);
+        chat_command.messages = command_messages;
+    }
+    let tools = match chat_command.user_installed {
+        true => None,
+        false => tools.session(ToolContext {
+            channel_id: &chat_command.channel_id,
+            guild_id: chat_command.guild_id.as_deref(),
+        }),
+    };
+    let job = AnswerJob {
+        tools,
+        persona: chat_command.persona.as_deref(),
+        ..job
+    };
+    let target = ReplyTarget::Followup {
+        interaction_token: &chat_command.interaction_token,
+    };
+    let sources = chat_command.source_links();
+    let request = build_chat_request(
+        client,
+        chat_command.topic.clone(),
+        chat_command.messages.clone(),
+        &chat_command.params,
+        &PromptOptions::from_env()?,
+    )
+    .await?;
+    stream_chat_completion(
+        client,
+        target,
+        chat_command.guild_id.as_deref(),
+        &request,
+        "",
+        &sources,
+        Some(job),
+    )
+    .await?;
+    Ok(())
+}
+
+/// Starts a thread named after the question and answers inside it, so the conversation
+/// can be continued there with `/chata`.
+async fn process_ask_command(
+    client: &reqwest::Client,
+    job: AnswerJob<'_>,
+    tools: &ToolRegistry,
+    ask_command: AskCommand,
+) -> Result<(), Error> {
+    let title = match generate_title(client, &ask_command.question).await {
+        Ok(title) if !title.is_empty() => title,
+        _ => ask_command.question.clone(),
+    };
+    let thread_name: String = title.chars().take(THREAD_NAME_MAX_LENGTH).collect();
+    let response = post_start_thread(client, &ask_command.channel_id, &thread_name).await?;
+    if !response.status().is_success() {
+        let err_text = response.text().await?;
+        error!("failed to start thread: {err_text:?}");
+        post_followup_message(
+            client,
+            &ask_command.interaction_token,
+            &WebhookRequest::new(err_text),
+        )
+        .await?;
+        return Ok(());
+    }
+    let thread = response.json::<Channel>().await?;
+    set_command_thread(
+        job.dynamo_client,
+        job.table_name,
+        job.command_id,
+        &thread.id,
+    )
+    .await?;
+    post_followup_message(
+        client,
+        &ask_command.interaction_token,
+        &WebhookRequest::new(format!("<#{}>", thread.id)),
+    )
+    .await?;
+
+    let chat_command = ChatCommand {
+        params: ask_command.params,
+        ..ChatCommand::new(
+            thread.id.clone(),
+            ask_command.interaction_token,
+            ask_command.topic,
+            vec![ChatCommandMessage::user(ask_command.question.clone())],
+        )
+    };
+    let request = build_chat_request(
+        client,
+        chat_command.topic,
+        chat_command.messages,
+        &chat_command.params,
+        &PromptOptions::from_env()?,
+    )
+    .await?;
+    let target = ReplyTarget::Channel {
+        channel_id: &thread.id,
+    };
+    let job = AnswerJob {
+        tools: tools.session(ToolContext {
+            channel_id: &thread.id,
+            guild_id: ask_command.guild_id.as_deref(),
+        }),
+        persona: ask_command.persona.as_deref(),
+        ..job
+    };
+    // Quote the question in the thread to keep it in the history of `/chata`
+    let prefix = format!("> {}\n\n", ask_command.question.replace('\n', "\n> "));
+    stream_chat_completion(
+        client,
+        target,
+        ask_command.guild_id.as_deref(),
+        &request,
+        &prefix,
+        &[],
+        Some(job),
+    )
+    .await?;
+    Ok(())
+}
+
+/// Generates the image and uploads it as a followup. Errors of the image API are posted
+/// as they are.
+async fn process_imagine_command(
+    client: &reqwest::Client,
+    imagine_command: ImagineCommand,
+) -> Result<(), Error> {
+    let token = &imagine_command.interaction_token;
+    let request = ImageGenerationRequest::new(&imagine_command.prompt, &imagine_command.size);
+    let response = post_image_generations(client, &request).await?;
+    if !response.status().is_success() {
+        let err_text = response.text().await?;
+        error!("image generation error response: {err_text:?}");
+        let message = format!("Failed to generate the image: {err_text}");
+        post_followup_message(client, token, &WebhookRequest::new(message)).await?;
+        return Ok(());
+    }
+    let response = response.json::<ImageGenerationResponse>().await?;
+    let (data, revised_prompt) = match response.data.into_iter().next() {
+        Some(ImageGenerationData {
+            b64_json: Some(b64_json),
+            revised_prompt,
+            ..
+        }) => (STANDARD.decode(b64_json)?, revised_prompt),
+        _ => {
+            let message = "Failed to generate the image: no image is returned";
+            post_followup_message(client, token, &WebhookRequest::new(message)).await?;
+            return Ok(());
+        }
+    };
+    let filename = "image.png".to_string();
+    let prompt = revised_prompt.unwrap_or(imagine_command.prompt);
+    let payload = WebhookRequest::new(format!("> {}", prompt.replace('\n', "\n> ")))
+        .with_attachments(vec![Attachment::upload(0, &filename)]);
+    let file = UploadFile {
+        filename,
+        content_type: "image/png".to_string(),
+        data,
+    };
+    let response = post_followup_message_with_files(client, token, &payload, &[file]).await?;
+    if !response.status().is_success() {
+        let err_text = response.text().await?;
+        error!("failed to upload the image: {err_text:?}");
+        let message = format!("Failed to upload the image: {err_text}");
+        post_followup_message(client, token, &WebhookRequest::new(message)).await?;
+    }
+    Ok(())
+}
+
+/// Replies with the transcripts of the audio attachments, headed by their filename when
+/// there are several.
+async fn process_transcribe_command(
+    client: &reqwest::Client,
+    transcribe_command: TranscribeCommand,
+) -> Result<(), Error> {
+    let token = &transcribe_command.interaction_token;
+    let Some(transcriber) = transcriber_from_env()? else {
+        let message = "Transcription is not enabled";
+        post_followup_message(client, token, &WebhookRequest::new(message)).await?;
+        return Ok(());
+    };
+    let several = transcribe_command.attachments.len() > 1;
+    let mut sections = Vec::new();
+    for attachment in transcribe_command.attachments.iter() {
+        let text = match transcribe_attachment(client, transcriber.as_ref(), attachment).await {
+            Ok(text) if text.is_empty() => "(no speech)".to_string(),
+            Ok(text) => text,
+            Err(err) => format!("Failed to transcribe the audio: {err}"),
+        };
+        match &attachment.filename {
+            Some(filename) if several => sections.push(format!("**{filename}**\n{text}")),
+            _ => sections.push(text),
+        }
+    }
+    let mut content = sections.join("\n\n");
+    if content.chars().count() > MESSAGE_CONTENT_MAX_LENGTH {
+        content = content
+            .chars()
+            .take(MESSAGE_CONTENT_MAX_LENGTH - 1)
+            .chain(['…'])
+            .collect();
+    }
+    post_followup_message(client, token, &WebhookRequest::new(content)).await?;
+    Ok(())
 }
 
 /// This is the main body for the function.
@@ -40,8 +297,15 @@ async fn function_handler(
     for record in event.payload.records.into_iter() {
         let record_box = Box::new(record.clone());
         let client = service.client.clone();
+        let dynamo_client = service.dynamo_client.clone();
+        let table_name = service.table_name.clone();
+        let tools = service.tools.clone();
         match record.event_name.as_str() {
-            // MODIFY is for replay usage
+            // MODIFY is for replay usage: changing a command answers it again. Updates by
+            // the Stop button or of the usage also arrive as MODIFY and are skipped.
+            "MODIFY" if is_status_update(&record.change) => {
+                info!("skip status update ({})", record_box.event_id);
+            }
             "INSERT" | "MODIFY" => {
                 set.spawn(async move {
                     let event_id = record_box.event_id.clone();
@@ -51,10 +315,6 @@ async fn function_handler(
                         error!("error occurred {e:?}");
                         event_id.clone()
                     };
-                    let map_err_json_event_id = |e| {
-                        error!("json serialize error occurred {e:?}");
-                        event_id.clone()
-                    };
 
                     let new_image = record.change.new_image;
                     let command_try: Result<DiscordCommand, _> = serde_dynamo::from_item(new_image);
@@ -66,70 +326,33 @@ async fn function_handler(
                         }
                     };
 
-                    match command.clone().command_type {
+                    if command.cancelled {
+                        info!("skip cancelled command ({})", command.id);
+                        return Ok(());
+                    }
+
+                    let job = AnswerJob {
+                        command_id: &command.id,
+                        dynamo_client: &dynamo_client,
+                        table_name: &table_name,
+                        tools: None,
+                        persona: None,
+                    };
+                    let result = match command.clone().command_type {
                         CommandType::Chat(chat_command) => {
-                            let response = post_chat_completions(
-                                &client,
-                                &ChatCompletionRequest::from(chat_command.clone()),
-                            )
-                            .await
-                            .map_err(map_err_event_id)?;
-
-                            let chat_response = if response.status().is_success() {
-                                response
-                            } else {
-                                let err_text =
-                                    response.text().await.map_err(map_err_json_event_id)?;
-                                error!("chatgpt error response: {err_text:?}");
-                                post_followup_message(
-                                    &client,
-                                    &chat_command.interaction_token,
-                                    &WebhookRequest { content: err_text },
-                                )
-                                .await
-                                .map_err(map_err_event_id)?;
-                                return Ok(());
-                            };
-                            let stream = response_extract_stream(chat_response, 10);
-                            pin_mut!(stream); // needed for iteration
-                            let mut buffer = String::new();
-                            let mut message: Option<Message> = None;
-                            while let Some(value) = stream.next().await {
-                                let value = value.map_err(|err| {
-                                    error!("stream error: {err:?}");
-                                    event_id.clone()
-                                })?;
-                                buffer.push_str(&value);
-                                if let Some(msg) = message.clone() {
-                                    let message_id = msg.id;
-                                    edit_followup_message(
-                                        &client,
-                                        &message_id,
-                                        &chat_command.interaction_token,
-                                        &WebhookRequest {
-                                            content: buffer.clone(),
-                                        },
-                                    )
-                                    .await
-                                    .map_err(map_err_event_id)?;
-                                } else {
-                                    let msg = post_followup_message(
-                                        &client,
-                                        &chat_command.interaction_token,
-                                        &WebhookRequest {
-                                            content: buffer.clone(),
-                                        },
-                                    )
-                                    .await
-                                    .map_err(map_err_event_id)?
-                                    .json::<Message>()
-                                    .await
-                                    .map_err(map_err_json_event_id)?;
-                                    message = Some(msg);
-                                }
-                            }
+                            process_chat_command(&client, job, &tools, chat_command).await
                         }
-                    }
+                        CommandType::Ask(ask_command) => {
+                            process_ask_command(&client, job, &tools, ask_command).await
+                        }
+                        CommandType::Imagine(imagine_command) => {
+                            process_imagine_command(&client, imagine_command).await
+                        }
+                        CommandType::Transcribe(transcribe_command) => {
+                            process_transcribe_command(&client, transcribe_command).await
+                        }
+                    };
+                    result.map_err(map_err_event_id)?;
 
                     println!("command: {command:?}");
                     info!("processed event ({event_id})");
@@ -164,8 +387,17 @@ async fn main() -> Result<(), Error> {
         .init();
 
     let client = Arc::new(reqwest::Client::new());
+    let config = aws_config::load_from_env().await;
+    let dynamo_client = Arc::new(aws_sdk_dynamodb::Client::new(&config));
+    let table_name = env::var("DISCORD_COMMAND_TABLE")?;
+    let tools = Arc::new(ToolRegistry::from_env()?);
 
-    let svs = &Service { client };
+    let svs = &Service {
+        client,
+        dynamo_client,
+        table_name,
+        tools,
+    };
 
     let service = ServiceFn::new(function_handler, svs);
     // Our Filter...
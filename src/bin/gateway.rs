@@ -0,0 +1,200 @@
+use std::{env, sync::Arc, time::Instant};
+
+use discord_chatbot::{
+    constants::DISCORD_GATEWAY_URL,
+    environment::{DISCORD_APPLICATION_ID, DISCORD_BOT_TOKEN},
+    error::Error,
+    gateway::{
+        channel_cache::ChannelCache, GatewayClient, INTENT_DIRECT_MESSAGES, INTENT_GUILDS,
+        INTENT_GUILD_MESSAGES, INTENT_MESSAGE_CONTENT,
+    },
+    models::discord::{channel::Channel, gateway::GatewayDispatch, message::Message},
+    services::{
+        conversation_service::{
+            build_chat_request, convert_messsages_to_chat_command_message, resolve_reply_chains,
+            HistoryFilter, PromptOptions,
+        },
+        discord_service::get_get_channel,
+        history_service::{
+            fetch_history, prepend_starter_message, starter_channel_id, HistoryLimits,
+        },
+        reply_service::{stream_chat_completion, ReplyTarget},
+        topic_service::get_channel_topic,
+    },
+};
+use tokio::sync::{mpsc::unbounded_channel, Mutex};
+use tracing::{error, info, instrument};
+
+/// Messages read as context when the bot is mentioned outside of its threads
+const DEFAULT_MENTION_HISTORY_LIMIT: u32 = 10;
+
+struct Service {
+    client: reqwest::Client,
+    channels: Mutex<ChannelCache>,
+    mention_history_limit: u32,
+}
+
+impl Service {
+    async fn get_channel(&self, channel_id: &str) -> Result<Channel, Error> {
+        if let Some(channel) = self.channels.lock().await.get(channel_id, Instant::now()) {
+            return Ok(channel);
+        }
+        let channel = get_get_channel(&self.client, channel_id)
+            .await?
+            .json::<Channel>()
+            .await?;
+        self.channels
+            .lock()
+            .await
+            .insert(channel.clone(), Instant::now());
+        Ok(channel)
+    }
+}
+
+/// Answers when the bot is mentioned or when a message is posted in a thread the bot
+/// started, e.g. by `/ask`.
+#[instrument(skip(service, message), fields(message_id = %message.id), err)]
+async fn handle_message_create(service: Arc<Service>, message: Message) -> Result<(), Error> {
+    if message.author.bot.unwrap_or(false) {
+        return Ok(());
+    }
+    let bot_id = DISCORD_APPLICATION_ID.unwrap();
+    let channel_id = message
+        .channel_id
+        .clone()
+        .ok_or("message without channel")?;
+    let mentioned = message.mentions.iter().any(|user| user.id == bot_id);
+    let channel = service.get_channel(&channel_id).await?;
+    // https://discord.com/developers/docs/resources/channel#channel-object-channel-types
+    let in_own_thread =
+        matches!(channel.type_, 10..=12) && channel.owner_id.as_deref() == Some(bot_id);
+    if !mentioned && !in_own_thread {
+        return Ok(());
+    }
+
+    let topic = get_channel_topic(&service.client, &channel).await?;
+    // The whole thread is read within the limits
+    let max_messages = match (topic.directives.history, in_own_thread) {
+        (Some(history), _) => Some(history),
+        (None, true) => None,
+        (None, false) => Some(service.mention_history_limit),
+    };
+    let filter = HistoryFilter::from_env()?;
+    let mut messages = fetch_history(
+        &service.client,
+        &channel_id,
+        None,
+        &HistoryLimits::from_env()?.with_max_messages(max_messages),
+        &filter,
+    )
+    .await?;
+    if topic.is_forum_post() {
+        let starter_channel_id = starter_channel_id(&channel, true);
+        prepend_starter_message(
+            &service.client,
+            &channel_id,
+            starter_channel_id,
+            &mut messages,
+        )
+        .await?;
+    }
+    let mut messages = resolve_reply_chains(&service.client, messages).await?;
+    filter.apply(&mut messages);
+    let sources: Vec<String> = messages
+        .iter()
+        .map(|m| Message::link(channel.guild_id.as_deref(), &channel_id, &m.id))
+        .collect();
+    let command_messages = convert_messsages_to_chat_command_message(messages);
+    let request = build_chat_request(
+        &service.client,
+        topic.with_post_context(topic.prompt.clone()),
+        command_messages,
+        &topic.directives.params,
+        &PromptOptions::from_env()?,
+    )
+    .await?;
+    let target = ReplyTarget::Channel {
+        channel_id: &channel_id,
+    };
+    stream_chat_completion(
+        &service.client,
+        target,
+        channel.guild_id.as_deref(),
+        &request,
+        "",
+        &sources,
+        None,
+    )
+    .await?;
+    Ok(())
+}
+
+async fn handle_dispatch(service: Arc<Service>, event: GatewayDispatch) -> Result<(), Error> {
+    match event.name.as_str() {
+        "MESSAGE_CREATE" => {
+            let message: Message = serde_json::from_value(event.data)?;
+            handle_message_create(service, message).await
+        }
+        "THREAD_CREATE" | "THREAD_UPDATE" | "CHANNEL_UPDATE" => {
+            let channel: Channel = serde_json::from_value(event.data)?;
+            service
+                .channels
+                .lock()
+                .await
+                .insert(channel, Instant::now());
+            Ok(())
+        }
+        // Deleted threads and channels only come with their id
+        "THREAD_DELETE" | "CHANNEL_DELETE" => {
+            let channel_id = event
+                .data
+                .get("id")
+                .and_then(|id| id.as_str())
+                .ok_or("deleted channel without id")?;
+            service.channels.lock().await.remove(channel_id);
+            Ok(())
+        }
+        _ => Ok(()),
+    }
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Error> {
+    tracing_subscriber::fmt()
+        .with_max_level(tracing::Level::INFO)
+        // disable printing the name of the module in every log line.
+        .with_target(false)
+        .with_file(true)
+        .with_line_number(true)
+        .init();
+
+    // Overridable to run against a local stand-in of the gateway
+    let gateway_url = env::var("DISCORD_GATEWAY_URL").unwrap_or(DISCORD_GATEWAY_URL.to_string());
+    let mention_history_limit = match env::var("GATEWAY_MENTION_HISTORY_LIMIT") {
+        Ok(limit) => limit.parse()?,
+        Err(_) => DEFAULT_MENTION_HISTORY_LIMIT,
+    };
+    let service = Arc::new(Service {
+        client: reqwest::Client::new(),
+        channels: Mutex::new(ChannelCache::default()),
+        mention_history_limit,
+    });
+
+    let intents =
+        INTENT_GUILDS | INTENT_GUILD_MESSAGES | INTENT_DIRECT_MESSAGES | INTENT_MESSAGE_CONTENT;
+    let gateway = GatewayClient::new(gateway_url, DISCORD_BOT_TOKEN.unwrap().to_string(), intents);
+    let (tx, mut rx) = unbounded_channel();
+    let gateway_task = tokio::spawn(gateway.run(tx));
+
+    while let Some(event) = rx.recv().await {
+        info!("dispatch: {}", event.name);
+        let service = service.clone();
+        tokio::spawn(async move {
+            if let Err(err) = handle_dispatch(service, event).await {
+                error!("error occurred {err:?}");
+            }
+        });
+    }
+
+    gateway_task.await?
+}
@@ -1,2 +1,52 @@
 pub const DISCORD_BASE_URL: &str = "https://discord.com/api";
+pub const DISCORD_GATEWAY_URL: &str = "wss://gateway.discord.gg";
 pub const CHATGPT_BASE_URL: &str = "https://api.openai.com/v1";
+pub const DEFAULT_CHAT_MODEL: &str = "gpt-3.5-turbo";
+// System prompt of channels without a persona or topic
+pub const DEFAULT_SYSTEM_PROMPT: &str = "You're concise";
+// `stream_options`, which reports the usage of streamed answers, needs 2024-10-21 or later
+pub const AZURE_OPENAI_DEFAULT_API_VERSION: &str = "2024-10-21";
+pub const DEFAULT_SIGNATURE_MAX_SKEW_SECONDS: i64 = 5 * 60;
+// https://discord.com/developers/docs/resources/channel#start-thread-without-message-json-params
+pub const THREAD_NAME_MAX_LENGTH: usize = 100;
+// Interaction tokens expire after 15 minutes, so no answer is streamed for longer
+pub const RUNNING_COMMAND_MAX_SECONDS: i64 = 15 * 60;
+// Index of the command table by ChannelId and CreatedAt, defined in template.yaml
+pub const COMMAND_CHANNEL_INDEX: &str = "ChannelIdCreatedAt";
+// Index of the command table by the ThreadId of `/ask` answers and CreatedAt
+pub const COMMAND_THREAD_INDEX: &str = "ThreadIdCreatedAt";
+pub const DEFAULT_STREAM_EDIT_MIN_INTERVAL_MS: u64 = 1000;
+pub const DEFAULT_STREAM_EDIT_MIN_CHARS: usize = 20;
+// https://discord.com/developers/docs/resources/channel#embed-object-embed-limits
+pub const EMBED_FIELD_VALUE_MAX_LENGTH: usize = 1024;
+pub const EMBED_DESCRIPTION_MAX_LENGTH: usize = 4096;
+pub const DEFAULT_ATTACHMENT_MAX_BYTES: u64 = 100 * 1024;
+pub const DEFAULT_PROMPT_TOKEN_BUDGET: u32 = 12_000;
+// Default, reply, chat input command and context menu command messages
+// https://discord.com/developers/docs/resources/message#message-object-message-types
+pub const DEFAULT_HISTORY_MESSAGE_TYPES: [u32; 4] = [0, 19, 20, 23];
+// Messages read above a reply, following what each one replies to
+pub const DEFAULT_REPLY_CHAIN_MAX_DEPTH: u32 = 5;
+// `{name}` is replaced with the display name of the author
+pub const DEFAULT_SPEAKER_PREFIX_FORMAT: &str = "{name}: ";
+// Tokens of a low detail image, https://platform.openai.com/docs/guides/vision
+pub const IMAGE_PART_ESTIMATED_TOKENS: u32 = 85;
+pub const DEFAULT_VISION_MODELS: &str = "gpt-4o,gpt-4o-mini,gpt-4-turbo,gpt-4.1,gpt-4.1-mini";
+// https://platform.openai.com/docs/guides/vision
+pub const IMAGE_ATTACHMENT_MAX_BYTES: u64 = 20 * 1024 * 1024;
+pub const DEFAULT_IMAGE_MODEL: &str = "dall-e-3";
+// https://platform.openai.com/docs/api-reference/images/create#images-create-size
+pub const IMAGE_SIZES: [&str; 3] = ["1024x1024", "1792x1024", "1024x1792"];
+// https://discord.com/developers/docs/resources/message#create-message-jsonform-params
+pub const MESSAGE_CONTENT_MAX_LENGTH: usize = 2000;
+pub const DEFAULT_TRANSCRIPTION_MODEL: &str = "whisper-1";
+// https://platform.openai.com/docs/guides/speech-to-text
+pub const AUDIO_ATTACHMENT_MAX_BYTES: u64 = 25 * 1024 * 1024;
+// Rounds of tool calls before the model has to answer
+pub const DEFAULT_TOOL_MAX_STEPS: u32 = 5;
+pub const TOOL_HISTORY_MAX_MESSAGES: u32 = 50;
+// https://discord.com/developers/docs/resources/message#get-channel-messages-query-string-params
+pub const GET_MESSAGES_MAX_LIMIT: u32 = 100;
+// Pages of messages read as the history of a conversation
+pub const DEFAULT_HISTORY_MAX_PAGES: u32 = 10;
+pub const HISTORY_MAX_MESSAGES: u32 = 1000;
@@ -45,6 +45,36 @@ pub fn chatgpt_completions_endpoint() -> String {
     format!("{CHATGPT_BASE_URL}/chat/completions",)
 }
 
+#[instrument(ret)]
+pub fn chatgpt_image_generations_endpoint() -> String {
+    format!("{CHATGPT_BASE_URL}/images/generations",)
+}
+
+/**
+ * https://platform.openai.com/docs/api-reference/audio/createTranscription
+ *
+ * `base_url` is the OpenAI API or a compatible server, e.g. a local Whisper server.
+ */
+#[instrument(ret)]
+pub fn audio_transcriptions_endpoint(base_url: &str) -> String {
+    format!("{}/audio/transcriptions", base_url.trim_end_matches('/'))
+}
+
+/**
+ * https://learn.microsoft.com/en-us/azure/ai-services/openai/reference#chat-completions
+ */
+#[instrument(ret)]
+pub fn azure_chat_completions_endpoint(
+    azure_endpoint: &str,
+    deployment: &str,
+    api_version: &str,
+) -> String {
+    format!(
+        "{}/openai/deployments/{deployment}/chat/completions?api-version={api_version}",
+        azure_endpoint.trim_end_matches('/')
+    )
+}
+
 #[instrument(ret)]
 pub fn get_channel_messages_endpoint(channel_id: &str) -> String {
     format!("{DISCORD_BASE_URL}/channels/{channel_id}/messages")
@@ -78,3 +108,18 @@ pub fn get_followup_item_endpoint(interaction_token: &str, message_id: &str) ->
 pub fn get_start_thread_endpoint(channel_id: &str) -> String {
     format!("{DISCORD_BASE_URL}/channels/{channel_id}/threads")
 }
+
+/**
+ * https://learn.microsoft.com/en-us/azure/ai-services/openai/reference#image-generation
+ */
+#[instrument(ret)]
+pub fn azure_image_generations_endpoint(
+    azure_endpoint: &str,
+    deployment: &str,
+    api_version: &str,
+) -> String {
+    format!(
+        "{}/openai/deployments/{deployment}/images/generations?api-version={api_version}",
+        azure_endpoint.trim_end_matches('/')
+    )
+}
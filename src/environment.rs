@@ -3,3 +3,8 @@ pub const DISCORD_BOT_TOKEN: Option<&'static str> = option_env!("DISCORD_BOT_TOK
 pub const DISCORD_BOT_PUBLIC_KEY: Option<&'static str> = option_env!("DISCORD_BOT_PUBLIC_KEY");
 
 pub const CHATGPT_API_KEY: Option<&'static str> = option_env!("CHATGPT_API_KEY");
+
+pub const AZURE_OPENAI_ENDPOINT: Option<&'static str> = option_env!("AZURE_OPENAI_ENDPOINT");
+pub const AZURE_OPENAI_API_KEY: Option<&'static str> = option_env!("AZURE_OPENAI_API_KEY");
+pub const AZURE_OPENAI_API_VERSION: Option<&'static str> = option_env!("AZURE_OPENAI_API_VERSION");
+pub const AZURE_OPENAI_DEPLOYMENTS: Option<&'static str> = option_env!("AZURE_OPENAI_DEPLOYMENTS");
@@ -0,0 +1,103 @@
+use std::{
+    collections::HashMap,
+    time::{Duration, Instant},
+};
+
+use crate::models::discord::channel::Channel;
+
+// Channels are read again after this long, in case an update was missed
+pub const CHANNEL_CACHE_TTL: Duration = Duration::from_secs(10 * 60);
+pub const CHANNEL_CACHE_MAX_ENTRIES: usize = 1000;
+
+/// Channels by id, each kept for `CHANNEL_CACHE_TTL`. The oldest one is dropped when the
+/// cache is full.
+#[derive(Debug, Default)]
+pub struct ChannelCache {
+    entries: HashMap<String, (Channel, Instant)>,
+}
+
+impl ChannelCache {
+    pub fn get(&mut self, channel_id: &str, now: Instant) -> Option<Channel> {
+        match self.entries.get(channel_id) {
+            Some((channel, cached_at)) if now.duration_since(*cached_at) < CHANNEL_CACHE_TTL => {
+                Some(channel.clone())
+            }
+            Some(_) => {
+                self.entries.remove(channel_id);
+                None
+            }
+            None => None,
+        }
+    }
+
+    pub fn insert(&mut self, channel: Channel, now: Instant) {
+        self.entries
+            .retain(|_, (_, cached_at)| now.duration_since(*cached_at) < CHANNEL_CACHE_TTL);
+        if self.entries.len() >= CHANNEL_CACHE_MAX_ENTRIES
+            && !self.entries.contains_key(&channel.id)
+        {
+            let oldest = self
+                .entries
+                .iter()
+                .min_by_key(|(_, (_, cached_at))| *cached_at)
+                .map(|(id, _)| id.clone());
+            if let Some(oldest) = oldest {
+                self.entries.remove(&oldest);
+            }
+        }
+        self.entries.insert(channel.id.clone(), (channel, now));
+    }
+
+    pub fn remove(&mut self, channel_id: &str) {
+        self.entries.remove(channel_id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn channel(id: &str) -> Channel {
+        serde_json::from_value(serde_json::json!({ "id": id, "type": 0 })).unwrap()
+    }
+
+    #[test]
+    fn expires_channels_after_the_ttl() {
+        let now = Instant::now();
+        let mut cache = ChannelCache::default();
+        cache.insert(channel("1"), now);
+        assert!(cache.get("1", now + CHANNEL_CACHE_TTL / 2).is_some());
+        assert!(cache.get("1", now + CHANNEL_CACHE_TTL).is_none());
+        assert!(cache.entries.is_empty());
+    }
+
+    #[test]
+    fn drops_the_oldest_channel_when_full() {
+        let now = Instant::now();
+        let mut cache = ChannelCache::default();
+        for i in 0..CHANNEL_CACHE_MAX_ENTRIES {
+            cache.insert(
+                channel(&i.to_string()),
+                now + Duration::from_millis(i as u64),
+            );
+        }
+        let later = now + Duration::from_secs(1);
+        cache.insert(channel("new"), later);
+        assert_eq!(cache.entries.len(), CHANNEL_CACHE_MAX_ENTRIES);
+        assert!(cache.get("0", later).is_none());
+        assert!(cache.get("1", later).is_some());
+        assert!(cache.get("new", later).is_some());
+        // Updating a cached channel drops nothing
+        cache.insert(channel("1"), later);
+        assert_eq!(cache.entries.len(), CHANNEL_CACHE_MAX_ENTRIES);
+    }
+
+    #[test]
+    fn removes_deleted_channels() {
+        let now = Instant::now();
+        let mut cache = ChannelCache::default();
+        cache.insert(channel("1"), now);
+        cache.remove("1");
+        assert!(cache.get("1", now).is_none());
+    }
+}
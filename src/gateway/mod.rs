@@ -0,0 +1,419 @@
+use std::time::Duration;
+
+use serde_json::{json, Value};
+use tokio::{
+    sync::mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender},
+    time::{interval_at, sleep, Instant},
+};
+use tracing::{debug, info, instrument, warn};
+
+use crate::{
+    error::Error,
+    models::discord::gateway::{
+        GatewayDispatch, GatewayHello, GatewayIdentify, GatewayIdentifyProperties, GatewayPayload,
+        GatewayReady, GatewayResume,
+    },
+};
+
+use self::websocket::{WsMessage, WsReader, WsWriter};
+
+pub mod channel_cache;
+pub mod websocket;
+
+/**
+ * https://discord.com/developers/docs/topics/opcodes-and-status-codes#gateway-gateway-opcodes
+ */
+const OP_DISPATCH: u8 = 0;
+const OP_HEARTBEAT: u8 = 1;
+const OP_IDENTIFY: u8 = 2;
+const OP_RESUME: u8 = 6;
+const OP_RECONNECT: u8 = 7;
+const OP_INVALID_SESSION: u8 = 9;
+const OP_HELLO: u8 = 10;
+const OP_HEARTBEAT_ACK: u8 = 11;
+
+/**
+ * https://discord.com/developers/docs/topics/opcodes-and-status-codes#gateway-gateway-close-event-codes
+ */
+const FATAL_CLOSE_CODES: [u16; 6] = [4004, 4010, 4011, 4012, 4013, 4014];
+/// Any close code other than 1000/1001 keeps the session resumable
+const RESUMABLE_CLOSE_CODE: u16 = 4000;
+const MAX_RECONNECT_BACKOFF_SECONDS: u64 = 60;
+
+/**
+ * https://discord.com/developers/docs/topics/gateway#gateway-intents
+ */
+pub const INTENT_GUILDS: u64 = 1 << 0;
+pub const INTENT_GUILD_MESSAGES: u64 = 1 << 9;
+pub const INTENT_DIRECT_MESSAGES: u64 = 1 << 12;
+pub const INTENT_MESSAGE_CONTENT: u64 = 1 << 15;
+
+#[derive(Debug)]
+struct Session {
+    id: String,
+    resume_url: String,
+}
+
+#[derive(Debug)]
+enum SessionEnd {
+    /// Reconnect and resume, e.g. after op 7 or a missed heartbeat ACK
+    Reconnect,
+    InvalidSession {
+        resumable: bool,
+    },
+    Closed(Option<u16>),
+    /// The receiver of dispatch events has been dropped
+    Stopped,
+}
+
+/// Minimal Discord Gateway client. It identifies, keeps the heartbeat, and resumes or
+/// re-identifies after disconnects while forwarding dispatch events to a channel.
+#[derive(Debug)]
+pub struct GatewayClient {
+    url: String,
+    token: String,
+    intents: u64,
+    session: Option<Session>,
+    seq: Option<u64>,
+}
+
+impl GatewayClient {
+    pub fn new<S: Into<String>>(url: S, token: S, intents: u64) -> Self {
+        Self {
+            url: url.into(),
+            token: token.into(),
+            intents,
+            session: None,
+            seq: None,
+        }
+    }
+
+    /// Runs until `events` is closed or the gateway rejects the connection for good.
+    pub async fn run(mut self, events: UnboundedSender<GatewayDispatch>) -> Result<(), Error> {
+        let mut backoff = 1;
+        loop {
+            match self.run_session(&events).await {
+                Ok(SessionEnd::Stopped) => return Ok(()),
+                Ok(SessionEnd::Reconnect) => {
+                    backoff = 1;
+                    continue;
+                }
+                Ok(SessionEnd::InvalidSession { resumable }) => {
+                    if !resumable {
+                        self.session = None;
+                        self.seq = None;
+                    }
+                    // https://discord.com/developers/docs/topics/gateway-events#invalid-session
+                    sleep(Duration::from_millis(1000 + rand::random::<u64>() % 4000)).await;
+                    continue;
+                }
+                Ok(SessionEnd::Closed(Some(code))) if FATAL_CLOSE_CODES.contains(&code) => {
+                    return Err(format!("gateway closed the connection: {code}").into());
+                }
+                Ok(SessionEnd::Closed(code)) => warn!("gateway closed the connection: {code:?}"),
+                Err(err) => warn!("gateway connection error: {err:?}"),
+            }
+            sleep(Duration::from_secs(backoff)).await;
+            backoff = (backoff * 2).min(MAX_RECONNECT_BACKOFF_SECONDS);
+        }
+    }
+
+    #[instrument(skip(self, events), ret, err)]
+    async fn run_session(
+        &mut self,
+        events: &UnboundedSender<GatewayDispatch>,
+    ) -> Result<SessionEnd, Error> {
+        let base_url = match &self.session {
+            Some(session) => session.resume_url.clone(),
+            None => self.url.clone(),
+        };
+        let url = format!("{}/?v=10&encoding=json", base_url.trim_end_matches('/'));
+        let (reader, mut writer) = websocket::connect(&url).await?;
+
+        // Reading is not cancel safe, so frames are read in a task of their own
+        let (tx, mut rx) = unbounded_channel();
+        let read_task = tokio::spawn(read_messages(reader, tx));
+        let result = self.drive_session(&mut rx, &mut writer, events).await;
+        read_task.abort();
+        if let Ok(SessionEnd::Reconnect) = result {
+            let _ = writer
+                .send(WsMessage::Close(Some(RESUMABLE_CLOSE_CODE)))
+                .await;
+        }
+        result
+    }
+
+    async fn drive_session(
+        &mut self,
+        rx: &mut UnboundedReceiver<Result<WsMessage, Error>>,
+        writer: &mut WsWriter,
+        events: &UnboundedSender<GatewayDispatch>,
+    ) -> Result<SessionEnd, Error> {
+        let hello = match rx.recv().await.ok_or("websocket reader stopped")?? {
+            WsMessage::Text(text) => serde_json::from_str::<GatewayPayload>(&text)?,
+            WsMessage::Close(code) => return Ok(SessionEnd::Closed(code)),
+            message => return Err(format!("expected hello: {message:?}").into()),
+        };
+        if hello.op != OP_HELLO {
+            return Err(format!("expected hello: {hello:?}").into());
+        }
+        let hello: GatewayHello = serde_json::from_value(hello.d.unwrap_or_default())?;
+        let heartbeat_interval = Duration::from_millis(hello.heartbeat_interval);
+
+        match (&self.session, self.seq) {
+            (Some(session), Some(seq)) => {
+                info!("resuming gateway session");
+                let resume = GatewayResume {
+                    token: self.token.clone(),
+                    session_id: session.id.clone(),
+                    seq,
+                };
+                send_payload(writer, OP_RESUME, serde_json::to_value(resume)?).await?;
+            }
+            _ => {
+                info!("identifying to gateway");
+                let identify = GatewayIdentify {
+                    token: self.token.clone(),
+                    intents: self.intents,
+                    properties: GatewayIdentifyProperties {
+                        os: std::env::consts::OS.to_string(),
+                        browser: env!("CARGO_PKG_NAME").to_string(),
+                        device: env!("CARGO_PKG_NAME").to_string(),
+                    },
+                };
+                send_payload(writer, OP_IDENTIFY, serde_json::to_value(identify)?).await?;
+            }
+        }
+
+        // The first heartbeat is sent after `heartbeat_interval * jitter`
+        let jitter = heartbeat_interval.mul_f64(rand::random::<f64>());
+        let mut heartbeat = interval_at(Instant::now() + jitter, heartbeat_interval);
+        let mut acked = true;
+        loop {
+            tokio::select! {
+                _ = heartbeat.tick() => {
+                    if !acked {
+                        warn!("heartbeat was not acknowledged");
+                        return Ok(SessionEnd::Reconnect);
+                    }
+                    acked = false;
+                    send_payload(writer, OP_HEARTBEAT, json!(self.seq)).await?;
+                }
+                message = rx.recv() => {
+                    let text = match message.ok_or("websocket reader stopped")?? {
+                        WsMessage::Text(text) => text,
+                        WsMessage::Ping(data) => {
+                            writer.send(WsMessage::Pong(data)).await?;
+                            continue;
+                        }
+                        WsMessage::Close(code) => return Ok(SessionEnd::Closed(code)),
+                        _ => continue,
+                    };
+                    let payload: GatewayPayload = serde_json::from_str(&text)?;
+                    if payload.s.is_some() {
+                        self.seq = payload.s;
+                    }
+                    match payload.op {
+                        OP_DISPATCH => {
+                            let name = payload.t.unwrap_or_default();
+                            let data = payload.d.unwrap_or_default();
+                            if name == "READY" {
+                                let ready: GatewayReady = serde_json::from_value(data.clone())?;
+                                info!("gateway ready as {}", ready.user.username);
+                                self.session = Some(Session {
+                                    id: ready.session_id,
+                                    resume_url: ready.resume_gateway_url,
+                                });
+                            }
+                            if events.send(GatewayDispatch { name, data }).is_err() {
+                                return Ok(SessionEnd::Stopped);
+                            }
+                        }
+                        OP_HEARTBEAT => {
+                            send_payload(writer, OP_HEARTBEAT, json!(self.seq)).await?;
+                        }
+                        OP_HEARTBEAT_ACK => acked = true,
+                        OP_RECONNECT => return Ok(SessionEnd::Reconnect),
+                        OP_INVALID_SESSION => {
+                            let resumable = payload.d.and_then(|d| d.as_bool()).unwrap_or(false);
+                            return Ok(SessionEnd::InvalidSession { resumable });
+                        }
+                        op => debug!("unhandled gateway opcode: {op}"),
+                    }
+                }
+            }
+        }
+    }
+}
+
+async fn read_messages(mut reader: WsReader, tx: UnboundedSender<Result<WsMessage, Error>>) {
+    loop {
+        let message = reader.read_message().await;
+        let end = matches!(message, Err(_) | Ok(WsMessage::Close(_)));
+        if tx.send(message).is_err() || end {
+            break;
+        }
+    }
+}
+
+async fn send_payload(writer: &mut WsWriter, op: u8, d: Value) -> Result<(), Error> {
+    let payload = serde_json::to_string(&GatewayPayload::new(op, d))?;
+    writer.send(WsMessage::Text(payload)).await
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::{net::TcpListener, task::JoinHandle, time::timeout};
+
+    use super::{websocket::stand_in::StandIn, *};
+
+    const TOKEN: &str = "token";
+    /// Long enough that no heartbeat is sent during a test, unless it sets a shorter one
+    const HEARTBEAT_INTERVAL_MS: u64 = 45_000;
+
+    async fn listen() -> (TcpListener, String) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("ws://{}", listener.local_addr().unwrap());
+        (listener, url)
+    }
+
+    fn start(
+        url: &str,
+    ) -> (
+        JoinHandle<Result<(), Error>>,
+        UnboundedReceiver<GatewayDispatch>,
+    ) {
+        let (tx, rx) = unbounded_channel();
+        let client = GatewayClient::new(url, TOKEN, INTENT_GUILDS);
+        (tokio::spawn(client.run(tx)), rx)
+    }
+
+    /// Accepts the next connection, failing the test if the client doesn't connect
+    async fn accept(listener: &TcpListener) -> StandIn {
+        timeout(Duration::from_secs(10), StandIn::accept(listener))
+            .await
+            .expect("client did not connect")
+    }
+
+    async fn send(server: &mut StandIn, payload: Value) {
+        server.send_text(&payload.to_string()).await;
+    }
+
+    async fn send_hello(server: &mut StandIn, heartbeat_interval: u64) {
+        let hello = json!({ "op": OP_HELLO, "d": { "heartbeat_interval": heartbeat_interval } });
+        send(server, hello).await;
+    }
+
+    async fn send_ready(server: &mut StandIn, resume_url: &str) {
+        let ready = json!({
+            "op": OP_DISPATCH,
+            "s": 1,
+            "t": "READY",
+            "d": {
+                "session_id": "session",
+                "resume_gateway_url": resume_url,
+                "user": { "id": "1", "username": "bot", "discriminator": "0" },
+            },
+        });
+        send(server, ready).await;
+    }
+
+    async fn receive(server: &mut StandIn) -> GatewayPayload {
+        loop {
+            if let WsMessage::Text(text) = server.read_message().await.unwrap() {
+                return serde_json::from_str(&text).unwrap();
+            }
+        }
+    }
+
+    /// Sends hello, expects identify and answers with READY
+    async fn open_session(server: &mut StandIn, resume_url: &str) {
+        send_hello(server, HEARTBEAT_INTERVAL_MS).await;
+        assert_eq!(receive(server).await.op, OP_IDENTIFY);
+        send_ready(server, resume_url).await;
+    }
+
+    #[tokio::test]
+    async fn identifies_and_forwards_dispatches() {
+        let (listener, url) = listen().await;
+        let (_client, mut events) = start(&url);
+        let mut server = accept(&listener).await;
+        send_hello(&mut server, HEARTBEAT_INTERVAL_MS).await;
+        let identify = receive(&mut server).await;
+        assert_eq!(identify.op, OP_IDENTIFY);
+        let identify: GatewayIdentify = serde_json::from_value(identify.d.unwrap()).unwrap();
+        assert_eq!(identify.token, TOKEN);
+        assert_eq!(identify.intents, INTENT_GUILDS);
+        send_ready(&mut server, &url).await;
+        assert_eq!(events.recv().await.unwrap().name, "READY");
+    }
+
+    #[tokio::test]
+    async fn reconnects_when_heartbeat_is_not_acknowledged() {
+        let (listener, url) = listen().await;
+        let (_client, _events) = start(&url);
+        let mut server = accept(&listener).await;
+        send_hello(&mut server, 50).await;
+        assert_eq!(receive(&mut server).await.op, OP_IDENTIFY);
+        // The heartbeat is left unanswered
+        assert_eq!(receive(&mut server).await.op, OP_HEARTBEAT);
+        let mut server = accept(&listener).await;
+        send_hello(&mut server, HEARTBEAT_INTERVAL_MS).await;
+        // Without READY there is no session to resume
+        assert_eq!(receive(&mut server).await.op, OP_IDENTIFY);
+    }
+
+    #[tokio::test]
+    async fn resumes_after_reconnect_request() {
+        let (listener, url) = listen().await;
+        let (_client, _events) = start(&url);
+        let mut server = accept(&listener).await;
+        open_session(&mut server, &url).await;
+        send(&mut server, json!({ "op": OP_RECONNECT, "d": null })).await;
+        let mut server = accept(&listener).await;
+        send_hello(&mut server, HEARTBEAT_INTERVAL_MS).await;
+        let resume = receive(&mut server).await;
+        assert_eq!(resume.op, OP_RESUME);
+        let resume: GatewayResume = serde_json::from_value(resume.d.unwrap()).unwrap();
+        assert_eq!(resume.session_id, "session");
+        assert_eq!(resume.seq, 1);
+    }
+
+    #[tokio::test]
+    async fn identifies_again_after_invalid_session() {
+        let (listener, url) = listen().await;
+        let (_client, _events) = start(&url);
+        let mut server = accept(&listener).await;
+        open_session(&mut server, &url).await;
+        send(&mut server, json!({ "op": OP_INVALID_SESSION, "d": false })).await;
+        let mut server = accept(&listener).await;
+        send_hello(&mut server, HEARTBEAT_INTERVAL_MS).await;
+        assert_eq!(receive(&mut server).await.op, OP_IDENTIFY);
+    }
+
+    #[tokio::test]
+    async fn stops_on_fatal_close_code() {
+        let (listener, url) = listen().await;
+        let (client, _events) = start(&url);
+        let mut server = accept(&listener).await;
+        send_hello(&mut server, HEARTBEAT_INTERVAL_MS).await;
+        assert_eq!(receive(&mut server).await.op, OP_IDENTIFY);
+        // Authentication failed
+        server.send_close(4004).await;
+        let result = timeout(Duration::from_secs(5), client).await.unwrap();
+        assert!(result.unwrap().is_err());
+    }
+
+    #[tokio::test]
+    async fn reconnects_after_resumable_close_code() {
+        let (listener, url) = listen().await;
+        let (_client, _events) = start(&url);
+        let mut server = accept(&listener).await;
+        open_session(&mut server, &url).await;
+        // Unknown error
+        server.send_close(4000).await;
+        let mut server = accept(&listener).await;
+        send_hello(&mut server, HEARTBEAT_INTERVAL_MS).await;
+        assert_eq!(receive(&mut server).await.op, OP_RESUME);
+    }
+}
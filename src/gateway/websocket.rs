@@ -0,0 +1,451 @@
+use std::sync::Arc;
+
+use base64::{engine::general_purpose::STANDARD, Engine};
+use reqwest::Url;
+use ring::digest::{digest, SHA1_FOR_LEGACY_USE_ONLY};
+use tokio::{
+    io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, ReadHalf, WriteHalf},
+    net::TcpStream,
+};
+use tokio_rustls::{
+    rustls::{ClientConfig, OwnedTrustAnchor, RootCertStore, ServerName},
+    TlsConnector,
+};
+use tracing::{debug, instrument};
+
+use crate::error::Error;
+
+/**
+ * https://www.rfc-editor.org/rfc/rfc6455#section-5.2
+ */
+const OPCODE_CONTINUATION: u8 = 0x0;
+const OPCODE_TEXT: u8 = 0x1;
+const OPCODE_BINARY: u8 = 0x2;
+const OPCODE_CLOSE: u8 = 0x8;
+const OPCODE_PING: u8 = 0x9;
+const OPCODE_PONG: u8 = 0xA;
+/// https://www.rfc-editor.org/rfc/rfc6455#section-1.3
+const ACCEPT_KEY_GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+/// Upper bound of the handshake response head, which is a few hundred bytes in practice
+const MAX_HANDSHAKE_HEAD_LENGTH: usize = 8 * 1024;
+/// Upper bound of a message, and so of a frame, read from the server. The largest gateway
+/// events, e.g. `GUILD_CREATE` of big guilds, stay well below it.
+const MAX_MESSAGE_LENGTH: u64 = 16 * 1024 * 1024;
+
+pub trait Stream: AsyncRead + AsyncWrite + Unpin + Send {}
+
+impl<T: AsyncRead + AsyncWrite + Unpin + Send> Stream for T {}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum WsMessage {
+    Text(String),
+    Binary(Vec<u8>),
+    Ping(Vec<u8>),
+    Pong(Vec<u8>),
+    /// Close frame with its status code, if any
+    Close(Option<u16>),
+}
+
+/// Opens a WebSocket connection to a `ws://` or `wss://` url and splits it into halves,
+/// so frames can be written while another task is waiting for the next one.
+#[instrument(err)]
+pub async fn connect(url: &str) -> Result<(WsReader, WsWriter), Error> {
+    let url = Url::parse(url)?;
+    let host = url
+        .host_str()
+        .ok_or("websocket url has no host")?
+        .to_string();
+    let port = url
+        .port_or_known_default()
+        .ok_or("websocket url has no port")?;
+    let tcp = TcpStream::connect((host.as_str(), port)).await?;
+    let stream: Box<dyn Stream> = match url.scheme() {
+        "ws" => Box::new(tcp),
+        "wss" => {
+            let mut root_store = RootCertStore::empty();
+            root_store.add_server_trust_anchors(webpki_roots::TLS_SERVER_ROOTS.0.iter().map(
+                |ta| {
+                    OwnedTrustAnchor::from_subject_spki_name_constraints(
+                        ta.subject,
+                        ta.spki,
+                        ta.name_constraints,
+                    )
+                },
+            ));
+            let config = ClientConfig::builder()
+                .with_safe_defaults()
+                .with_root_certificates(root_store)
+                .with_no_client_auth();
+            let server_name = ServerName::try_from(host.as_str())?;
+            let tls = TlsConnector::from(Arc::new(config))
+                .connect(server_name, tcp)
+                .await?;
+            Box::new(tls)
+        }
+        scheme => return Err(format!("unsupported websocket scheme: {scheme}").into()),
+    };
+    handshake(stream, &url, &host).await
+}
+
+/// Performs the opening handshake on an already connected stream.
+pub async fn handshake(
+    mut stream: Box<dyn Stream>,
+    url: &Url,
+    host: &str,
+) -> Result<(WsReader, WsWriter), Error> {
+    let key = STANDARD.encode(rand::random::<[u8; 16]>());
+    let path = match url.query() {
+        Some(query) => format!("{}?{query}", url.path()),
+        None => url.path().to_string(),
+    };
+    let request = format!(
+        "GET {path} HTTP/1.1\r\n\
+         Host: {host}\r\n\
+         Upgrade: websocket\r\n\
+         Connection: Upgrade\r\n\
+         Sec-WebSocket-Key: {key}\r\n\
+         Sec-WebSocket-Version: 13\r\n\r\n"
+    );
+    stream.write_all(request.as_bytes()).await?;
+
+    // Read the response head byte by byte so no frame data is consumed
+    let head = read_head(&mut stream).await?;
+    let status_line = head.lines().next().unwrap_or_default();
+    if !status_line.contains(" 101 ") {
+        return Err(format!("websocket upgrade failed: {status_line}").into());
+    }
+    let accept = header_value(&head, "Sec-WebSocket-Accept");
+    if accept != Some(accept_key(&key).as_str()) {
+        return Err(format!("invalid Sec-WebSocket-Accept: {accept:?}").into());
+    }
+    debug!("websocket connected: {url}");
+
+    let (reader, writer) = tokio::io::split(stream);
+    Ok((
+        WsReader {
+            inner: reader,
+            fragments: None,
+        },
+        WsWriter { inner: writer },
+    ))
+}
+
+/// Reads an HTTP head up to the empty line, at most `MAX_HANDSHAKE_HEAD_LENGTH` bytes
+async fn read_head<S: AsyncRead + Unpin>(stream: &mut S) -> Result<String, Error> {
+    let mut head = Vec::new();
+    while !head.ends_with(b"\r\n\r\n") {
+        if head.len() >= MAX_HANDSHAKE_HEAD_LENGTH {
+            return Err("websocket handshake response is too long".into());
+        }
+        head.push(stream.read_u8().await?);
+    }
+    Ok(String::from_utf8_lossy(&head).into_owned())
+}
+
+fn header_value<'a>(head: &'a str, name: &str) -> Option<&'a str> {
+    head.lines().find_map(|line| {
+        let (key, value) = line.split_once(':')?;
+        key.trim().eq_ignore_ascii_case(name).then(|| value.trim())
+    })
+}
+
+/// `Sec-WebSocket-Accept` the server answers `key` with
+fn accept_key(key: &str) -> String {
+    let hash = digest(
+        &SHA1_FOR_LEGACY_USE_ONLY,
+        format!("{key}{ACCEPT_KEY_GUID}").as_bytes(),
+    );
+    STANDARD.encode(hash.as_ref())
+}
+
+pub struct WsReader {
+    inner: ReadHalf<Box<dyn Stream>>,
+    fragments: Option<(u8, Vec<u8>)>,
+}
+
+impl WsReader {
+    /// Reads the next complete message, joining fragmented frames.
+    pub async fn read_message(&mut self) -> Result<WsMessage, Error> {
+        loop {
+            let (fin, opcode, payload) = self.read_frame().await?;
+            let (opcode, payload) = match opcode {
+                OPCODE_CONTINUATION => {
+                    let (first_opcode, mut buffer) = self
+                        .fragments
+                        .take()
+                        .ok_or("continuation frame without a first frame")?;
+                    if (buffer.len() + payload.len()) as u64 > MAX_MESSAGE_LENGTH {
+                        return Err("websocket message is too long".into());
+                    }
+                    buffer.extend_from_slice(&payload);
+                    if !fin {
+                        self.fragments = Some((first_opcode, buffer));
+                        continue;
+                    }
+                    (first_opcode, buffer)
+                }
+                OPCODE_TEXT | OPCODE_BINARY if !fin => {
+                    self.fragments = Some((opcode, payload));
+                    continue;
+                }
+                _ => (opcode, payload),
+            };
+            return match opcode {
+                OPCODE_TEXT => Ok(WsMessage::Text(String::from_utf8(payload)?)),
+                OPCODE_BINARY => Ok(WsMessage::Binary(payload)),
+                OPCODE_PING => Ok(WsMessage::Ping(payload)),
+                OPCODE_PONG => Ok(WsMessage::Pong(payload)),
+                OPCODE_CLOSE => {
+                    let code =
+                        (payload.len() >= 2).then(|| u16::from_be_bytes([payload[0], payload[1]]));
+                    Ok(WsMessage::Close(code))
+                }
+                _ => Err(format!("unknown websocket opcode: {opcode}").into()),
+            };
+        }
+    }
+
+    async fn read_frame(&mut self) -> Result<(bool, u8, Vec<u8>), Error> {
+        let mut header = [0u8; 2];
+        self.inner.read_exact(&mut header).await?;
+        let fin = header[0] & 0x80 != 0;
+        let opcode = header[0] & 0x0F;
+        let masked = header[1] & 0x80 != 0;
+        let len = match header[1] & 0x7F {
+            126 => self.inner.read_u16().await? as u64,
+            127 => self.inner.read_u64().await?,
+            len => len as u64,
+        };
+        if len > MAX_MESSAGE_LENGTH {
+            return Err(format!("websocket frame is too long: {len} bytes").into());
+        }
+        let mask = if masked {
+            let mut mask = [0u8; 4];
+            self.inner.read_exact(&mut mask).await?;
+            Some(mask)
+        } else {
+            None
+        };
+        let mut payload = vec![0u8; usize::try_from(len)?];
+        self.inner.read_exact(&mut payload).await?;
+        if let Some(mask) = mask {
+            apply_mask(&mut payload, mask);
+        }
+        Ok((fin, opcode, payload))
+    }
+}
+
+pub struct WsWriter {
+    inner: WriteHalf<Box<dyn Stream>>,
+}
+
+impl WsWriter {
+    pub async fn send(&mut self, message: WsMessage) -> Result<(), Error> {
+        let (opcode, payload) = match message {
+            WsMessage::Text(text) => (OPCODE_TEXT, text.into_bytes()),
+            WsMessage::Binary(data) => (OPCODE_BINARY, data),
+            WsMessage::Ping(data) => (OPCODE_PING, data),
+            WsMessage::Pong(data) => (OPCODE_PONG, data),
+            WsMessage::Close(code) => (
+                OPCODE_CLOSE,
+                code.map(|c| c.to_be_bytes().to_vec()).unwrap_or_default(),
+            ),
+        };
+        self.write_frame(opcode, payload).await
+    }
+
+    /// Clients must mask every frame they send.
+    async fn write_frame(&mut self, opcode: u8, mut payload: Vec<u8>) -> Result<(), Error> {
+        let mut frame = vec![0x80 | opcode];
+        match payload.len() {
+            len if len < 126 => frame.push(0x80 | len as u8),
+            len if len <= u16::MAX as usize => {
+                frame.push(0x80 | 126);
+                frame.extend_from_slice(&(len as u16).to_be_bytes());
+            }
+            len => {
+                frame.push(0x80 | 127);
+                frame.extend_from_slice(&(len as u64).to_be_bytes());
+            }
+        }
+        let mask = rand::random::<[u8; 4]>();
+        frame.extend_from_slice(&mask);
+        apply_mask(&mut payload, mask);
+        frame.extend_from_slice(&payload);
+        self.inner.write_all(&frame).await?;
+        self.inner.flush().await?;
+        Ok(())
+    }
+}
+
+fn apply_mask(payload: &mut [u8], mask: [u8; 4]) {
+    for (i, byte) in payload.iter_mut().enumerate() {
+        *byte ^= mask[i % 4];
+    }
+}
+
+/// Server side of a connection on a local listener, standing in for the gateway in tests
+#[cfg(test)]
+pub(crate) mod stand_in {
+    use tokio::net::TcpListener;
+
+    use super::*;
+
+    pub struct StandIn {
+        reader: WsReader,
+        writer: WriteHalf<Box<dyn Stream>>,
+    }
+
+    impl StandIn {
+        /// Accepts the next connection and answers its handshake
+        pub async fn accept(listener: &TcpListener) -> Self {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let head = read_head(&mut stream).await.unwrap();
+            let key = header_value(&head, "Sec-WebSocket-Key").unwrap();
+            let response = format!(
+                "HTTP/1.1 101 Switching Protocols\r\n\
+                 Upgrade: websocket\r\n\
+                 Connection: Upgrade\r\n\
+                 Sec-WebSocket-Accept: {}\r\n\r\n",
+                accept_key(key)
+            );
+            stream.write_all(response.as_bytes()).await.unwrap();
+            let stream: Box<dyn Stream> = Box::new(stream);
+            let (reader, writer) = tokio::io::split(stream);
+            Self {
+                reader: WsReader {
+                    inner: reader,
+                    fragments: None,
+                },
+                writer,
+            }
+        }
+
+        pub async fn read_message(&mut self) -> Result<WsMessage, Error> {
+            self.reader.read_message().await
+        }
+
+        /// Writes `header` as it is, e.g. to announce a frame which is never sent
+        pub async fn send_raw(&mut self, header: &[u8]) {
+            self.writer.write_all(header).await.unwrap();
+        }
+
+        /// Sends an unmasked frame, as servers do
+        pub async fn send_frame(&mut self, fin: bool, opcode: u8, payload: &[u8]) {
+            let mut frame = vec![if fin { 0x80 | opcode } else { opcode }];
+            match payload.len() {
+                len if len < 126 => frame.push(len as u8),
+                len => {
+                    frame.push(126);
+                    frame.extend_from_slice(&(len as u16).to_be_bytes());
+                }
+            }
+            frame.extend_from_slice(payload);
+            self.send_raw(&frame).await;
+        }
+
+        pub async fn send_text(&mut self, text: &str) {
+            self.send_frame(true, OPCODE_TEXT, text.as_bytes()).await;
+        }
+
+        pub async fn send_close(&mut self, code: u16) {
+            self.send_frame(true, OPCODE_CLOSE, &code.to_be_bytes())
+                .await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::net::TcpListener;
+
+    use super::{stand_in::StandIn, *};
+
+    async fn listen() -> (TcpListener, String) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("ws://{}/", listener.local_addr().unwrap());
+        (listener, url)
+    }
+
+    #[test]
+    fn computes_accept_key() {
+        // https://www.rfc-editor.org/rfc/rfc6455#section-1.3
+        assert_eq!(
+            accept_key("dGhlIHNhbXBsZSBub25jZQ=="),
+            "s3pPLMBiTxaQ9kYGzzhZRbK+xOo="
+        );
+    }
+
+    #[tokio::test]
+    async fn exchanges_masked_and_fragmented_messages() {
+        let (listener, url) = listen().await;
+        let server = tokio::spawn(async move {
+            let mut server = StandIn::accept(&listener).await;
+            let received = server.read_message().await.unwrap();
+            server.send_frame(false, OPCODE_TEXT, b"hel").await;
+            server.send_frame(false, OPCODE_CONTINUATION, b"lo ").await;
+            server.send_frame(true, OPCODE_CONTINUATION, b"back").await;
+            received
+        });
+        let (mut reader, mut writer) = connect(&url).await.unwrap();
+        writer
+            .send(WsMessage::Text("hello".to_string()))
+            .await
+            .unwrap();
+        assert_eq!(
+            reader.read_message().await.unwrap(),
+            WsMessage::Text("hello back".to_string())
+        );
+        assert_eq!(server.await.unwrap(), WsMessage::Text("hello".to_string()));
+    }
+
+    #[tokio::test]
+    async fn rejects_wrong_accept_key() {
+        let (listener, url) = listen().await;
+        tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            read_head(&mut stream).await.unwrap();
+            stream
+                .write_all(
+                    b"HTTP/1.1 101 Switching Protocols\r\n\
+                      Sec-WebSocket-Accept: s3pPLMBiTxaQ9kYGzzhZRbK+xOo=\r\n\r\n",
+                )
+                .await
+                .unwrap();
+        });
+        assert!(connect(&url).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn rejects_endless_handshake_head() {
+        let (listener, url) = listen().await;
+        tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            read_head(&mut stream).await.unwrap();
+            stream
+                .write_all(b"HTTP/1.1 101 Switching Protocols\r\n")
+                .await
+                .unwrap();
+            let header = b"X-Padding: aaaaaaaaaaaaaaaaaaaaaaaaaaaaaa\r\n";
+            // Stops once the client has given up
+            while stream.write_all(header).await.is_ok() {}
+        });
+        assert!(connect(&url).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn rejects_oversized_frame() {
+        let (listener, url) = listen().await;
+        tokio::spawn(async move {
+            let mut server = StandIn::accept(&listener).await;
+            // A 1 TiB text frame, of which no payload follows
+            let mut header = vec![0x80 | OPCODE_TEXT, 127];
+            header.extend_from_slice(&(1u64 << 40).to_be_bytes());
+            server.send_raw(&header).await;
+            // Keep the connection open until the client has read the header
+            let _ = server.read_message().await;
+        });
+        let (mut reader, _writer) = connect(&url).await.unwrap();
+        assert!(reader.read_message().await.is_err());
+    }
+}
@@ -2,6 +2,9 @@ pub mod constants;
 pub mod endpoint;
 pub mod environment;
 pub mod error;
+#[cfg(feature = "gateway")]
+pub mod gateway;
 pub mod models;
 pub mod service;
 pub mod services;
+pub mod signature;
@@ -1,26 +1,48 @@
-use std::{
-    env,
-    str::{from_utf8, FromStr},
-    sync::Arc,
-};
+use std::{env, sync::Arc};
 
 use chrono::Utc;
 use discord_chatbot::{
-    environment::DISCORD_APPLICATION_ID,
+    constants::{DEFAULT_SYSTEM_PROMPT, IMAGE_SIZES, MESSAGE_CONTENT_MAX_LENGTH},
     models::{
+        chatgpt::chat_completion::GenerationParams,
         discord::{
-            channel::Channel,
+            component::Component,
             message::Message,
-            request::{CommandInteractionOptionValue, InteractionRequest},
-            response::{InteractionMessage, InteractionResponse},
+            request::{
+                CommandInteractionOption, CommandInteractionOptionValue, InteractionRequest,
+            },
+            response::{InteractionMessage, InteractionModal, InteractionResponse},
+        },
+        dynamo::{
+            defaults::Defaults,
+            discord_command::{ChatCommandMessage, DiscordCommand, HistoryRequest},
+            persona::{ActivePersona, Persona},
+        },
+    },
+    service::ServiceFn,
+    services::{
+        conversation_service::convert_messsages_to_chat_command_message,
+        dynamo_service::{
+            delete_active_persona, delete_defaults, find_running_commands, get_command,
+            get_defaults, list_personas, put_active_persona, put_command, put_defaults,
+            put_persona, set_command_cancelled,
         },
-        dynamo::discord_command::{ChatCommandMessage, DiscordCommand},
+        generation_service::{params_from_options, resolve_params},
+        history_service::starter_channel_id,
+        persona_service::{find_persona, resolve_persona, PersonaPlace},
+        reply_service::AnswerButton,
+        topic_service::get_interaction_channel,
+        transcription_service::is_audio_attachment,
     },
-    services::discord_service::{get_get_channel, get_get_messages},
+    signature::{SignatureValidator, VerifySignatureLayer},
 };
-use ed25519_dalek::{PublicKey, Signature};
 use environment::DISCORD_BOT_PUBLIC_KEY;
-use lambda_http::{http::Method, run, service_fn, Body, Error, Request, RequestExt, Response};
+use lambda_http::{
+    http::Method,
+    run,
+    tower::{Layer, ServiceExt},
+    Body, Error, Request, RequestExt, Response,
+};
 use tracing::{error, info, instrument};
 
 pub mod constants;
@@ -35,48 +57,6 @@ fn get_response(_req: &Request) -> Result<Response<Body>, Error> {
     Ok(Response::new(Body::from("Hello world!")))
 }
 
-fn convert_messsages_to_chat_command_message(messages: Vec<Message>) -> Vec<ChatCommandMessage> {
-    let mut results = Vec::new();
-
-    let mut prev = None;
-    for msg in &messages {
-        let content = msg.clone().get_message_content().unwrap_or("".to_string());
-        let mut cmd_message = if msg.author.id == DISCORD_APPLICATION_ID.unwrap() {
-            ChatCommandMessage::assistant(content)
-        } else {
-            ChatCommandMessage::user(content)
-        };
-        if let Some(p) = prev {
-            match p {
-                ChatCommandMessage::User { content } => {
-                    if let ChatCommandMessage::User {
-                        content: current_content,
-                    } = cmd_message
-                    {
-                        cmd_message =
-                            ChatCommandMessage::user(format!("{content}\n{current_content}"));
-                        results.pop();
-                    }
-                }
-                ChatCommandMessage::Assistant { content } => {
-                    if let ChatCommandMessage::Assistant {
-                        content: current_content,
-                    } = cmd_message
-                    {
-                        cmd_message =
-                            ChatCommandMessage::assistant(format!("{content}\n{current_content}"));
-                        results.pop();
-                    }
-                }
-            }
-        }
-        prev = Some(cmd_message.clone());
-        results.push(cmd_message);
-    }
-
-    results
-}
-
 #[instrument(skip(http_client, dynamo_client), ret, err)]
 async fn post_interactions_handler(
     req: &Request,
@@ -105,45 +85,127 @@ async fn post_interactions_handler(
                 .unwrap())
         }
         2u32 => {
+            let user_id = request.invoker().map(|user| user.id.clone());
+            // Where the app is installed to the user only, the bot can't read the channel
+            // and settings are saved per user
+            let user_installed = !request.has_channel_access();
+            let Some(channel_id) = request.channel_id().map(str::to_string) else {
+                let response = InteractionResponse::new(
+                    4,
+                    Some(InteractionMessage::new("This command can't be used here").ephemeral()),
+                );
+                return Ok(Response::builder()
+                    .status(200)
+                    .header("content-type", "application/json")
+                    .body(Body::from(serde_json::to_string(&response)?))
+                    .unwrap());
+            };
+            let (channel, channel_topic) = get_interaction_channel(http_client, &request).await?;
+            info!("channel: {channel:?}");
+            let place = PersonaPlace::of_interaction(&request, &channel)?;
             let data = request.data.unwrap();
-            let channel_id = request.channel_id.unwrap();
             let now = Utc::now().timestamp_millis();
-            let channel = get_get_channel(http_client, &channel_id)
-                .await?
-                .json::<Channel>()
-                .await?;
-            info!("channel: {channel:?}");
-            let topic = if channel.type_ == 0u32 {
-                channel.topic
-            } else if let Some(p_channel_id) = channel.parent_id {
-                let parent_channel = get_get_channel(http_client, &p_channel_id)
+            let persona_table = env::var("PERSONA_TABLE").ok();
+            let requested_persona = CommandInteractionOption::find_string(
+                data.options.as_deref().unwrap_or_default(),
+                "persona",
+            );
+            let persona = match &persona_table {
+                Some(table_name) => {
+                    resolve_persona(
+                        dynamo_client,
+                        table_name,
+                        &place,
+                        requested_persona.as_deref(),
+                    )
                     .await?
-                    .json::<Channel>()
-                    .await?;
-                parent_channel.topic
-            } else {
-                None
+                }
+                None => None,
             };
+            if let (Some(name), None) = (&requested_persona, &persona) {
+                let content = match persona_table {
+                    Some(_) => format!("Unknown persona: {name}"),
+                    None => "Personas are not enabled".to_string(),
+                };
+                let response =
+                    InteractionResponse::new(4, Some(InteractionMessage::new(content).ephemeral()));
+                return Ok(Response::builder()
+                    .status(200)
+                    .header("content-type", "application/json")
+                    .body(Body::from(serde_json::to_string(&response)?))
+                    .unwrap());
+            }
+            // The persona replaces the prompt of the channel topic, whose directives
+            // still apply
+            let persona_name = persona.as_ref().map(|r| r.persona.name.clone());
+            let topic = channel_topic.with_post_context(match &persona {
+                Some(resolved) => Some(resolved.persona.prompt.clone()),
+                None => channel_topic.prompt.clone(),
+            });
+            let command_params =
+                match params_from_options(data.options.as_deref().unwrap_or_default()) {
+                    Ok(params) => params,
+                    Err(reason) => {
+                        let response = InteractionResponse::new(
+                            4,
+                            Some(
+                                InteractionMessage::new(format!("Invalid option: {reason}"))
+                                    .ephemeral(),
+                            ),
+                        );
+                        return Ok(Response::builder()
+                            .status(200)
+                            .header("content-type", "application/json")
+                            .body(Body::from(serde_json::to_string(&response)?))
+                            .unwrap());
+                    }
+                };
+            let params = resolve_params(
+                dynamo_client,
+                persona_table.as_deref(),
+                place.defaults_scope().as_deref(),
+                &channel_topic.directives.params,
+                command_params,
+            )
+            .await?;
+            if user_installed && matches!(data.name.as_str(), "chat" | "chats" | "chata") {
+                let response = InteractionResponse::new(
+                    4,
+                    Some(
+                        InteractionMessage::new(
+                            "Cannot read the messages here. Use /ask, or Summarize on a message",
+                        )
+                        .ephemeral(),
+                    ),
+                );
+                return Ok(Response::builder()
+                    .status(200)
+                    .header("content-type", "application/json")
+                    .body(Body::from(serde_json::to_string(&response)?))
+                    .unwrap());
+            }
             match data.name.as_str() {
                 "chat" => {
-                    let messages = get_get_messages(http_client, &channel_id, None, Some(1))
-                        .await?
-                        .json::<Vec<Message>>()
-                        .await?;
-                    let message = messages.first().unwrap();
-                    info!("message: {message:?}");
-                    let content = message.content.clone().unwrap();
+                    // The latest message, and the ones it replies to, are read by the
+                    // worker after the interaction is deferred
                     let res = dynamo_client
                         .put_item()
                         .table_name(env::var("DISCORD_COMMAND_TABLE")?)
-                        .set_item(Some(serde_dynamo::to_item(DiscordCommand::chat_command(
-                            &request.id,
-                            &channel_id,
-                            &request.token,
-                            topic,
-                            vec![ChatCommandMessage::user(content)],
-                            now,
-                        ))?))
+                        .set_item(Some(serde_dynamo::to_item(
+                            DiscordCommand::chat_command(
+                                &request.id,
+                                &channel_id,
+                                &request.token,
+                                topic,
+                                Vec::new(),
+                                now,
+                            )
+                            .with_user_id(user_id.clone())
+                            .with_guild_id(request.guild_id.clone())
+                            .with_history(Some(HistoryRequest::new(&request.id, Some(1))))
+                            .with_persona(persona_name)
+                            .with_params(params),
+                        )?))
                         .send()
                         .await?;
                     let response = InteractionResponse::new(5, Option::<String>::None);
@@ -154,7 +216,7 @@ async fn post_interactions_handler(
                         .unwrap())
                 }
                 "chats" => {
-                    let default_limit = 3;
+                    let default_limit = channel_topic.directives.history.unwrap_or(3);
                     let limit_count = if let Some(options) = data.options {
                         if let Some(opt) = options.iter().find(|o| o.name == "n") {
                             match opt.value.clone().unwrap() {
@@ -167,26 +229,187 @@ async fn post_interactions_handler(
                     } else {
                         default_limit
                     };
-                    let mut messages =
-                        get_get_messages(http_client, &channel_id, None, Some(limit_count))
-                            .await?
-                            .json::<Vec<Message>>()
-                            .await?;
-                    messages.reverse();
-                    let command_messages = convert_messsages_to_chat_command_message(messages);
+                    let history = HistoryRequest::new(&request.id, Some(limit_count));
                     let res = dynamo_client
                         .put_item()
                         .table_name(env::var("DISCORD_COMMAND_TABLE")?)
-                        .set_item(Some(serde_dynamo::to_item(DiscordCommand::chat_command(
+                        .set_item(Some(serde_dynamo::to_item(
+                            DiscordCommand::chat_command(
+                                &request.id,
+                                &channel_id,
+                                &request.token,
+                                topic,
+                                Vec::new(),
+                                now,
+                            )
+                            .with_user_id(user_id.clone())
+                            .with_guild_id(request.guild_id.clone())
+                            .with_history(Some(history))
+                            .with_persona(persona_name)
+                            .with_params(params),
+                        )?))
+                        .send()
+                        .await?;
+                    let response = InteractionResponse::new(5, Option::<String>::None);
+                    Ok(Response::builder()
+                        .status(200)
+                        .header("content-type", "application/json")
+                        .body(Body::from(serde_json::to_string(&response)?))
+                        .unwrap())
+                }
+                "ask" => {
+                    let question = data
+                        .options
+                        .unwrap_or_default()
+                        .into_iter()
+                        .find(|o| o.name == "question")
+                        .and_then(|o| match o.value {
+                            Some(CommandInteractionOptionValue::String(s)) => Some(s),
+                            _ => None,
+                        })
+                        .ok_or("question is required")?;
+                    // Threads can't be started without access to the channel, so the
+                    // question is answered in the followup instead
+                    let command = if user_installed {
+                        DiscordCommand::chat_command(
+                            &request.id,
+                            &channel_id,
+                            &request.token,
+                            topic,
+                            vec![ChatCommandMessage::user(question)],
+                            now,
+                        )
+                        .with_user_installed(true)
+                    } else {
+                        DiscordCommand::ask_command(
+                            &request.id,
+                            &channel_id,
+                            &request.token,
+                            topic,
+                            &question,
+                            now,
+                        )
+                    };
+                    put_command(
+                        dynamo_client,
+                        &env::var("DISCORD_COMMAND_TABLE")?,
+                        &command
+                            .with_user_id(user_id.clone())
+                            .with_guild_id(request.guild_id.clone())
+                            .with_persona(persona_name)
+                            .with_params(params),
+                    )
+                    .await?;
+                    let response = InteractionResponse::new(5, Option::<String>::None);
+                    Ok(Response::builder()
+                        .status(200)
+                        .header("content-type", "application/json")
+                        .body(Body::from(serde_json::to_string(&response)?))
+                        .unwrap())
+                }
+                "prompt" => {
+                    let modal = InteractionModal {
+                        custom_id: "prompt".to_string(),
+                        title: "Prompt".to_string(),
+                        components: vec![
+                            Component::action_row(vec![Component::text_input(
+                                2, "Prompt", "prompt", true, 4000,
+                            )]),
+                            Component::action_row(vec![Component::text_input(
+                                2,
+                                "System prompt (defaults to the channel topic)",
+                                "system",
+                                false,
+                                4000,
+                            )]),
+                        ],
+                    };
+                    let response = InteractionResponse::new(9, Some(modal));
+                    Ok(Response::builder()
+                        .status(200)
+                        .header("content-type", "application/json")
+                        .body(Body::from(serde_json::to_string(&response)?))
+                        .unwrap())
+                }
+                "imagine" => {
+                    let options = data.options.unwrap_or_default();
+                    let option = |name: &str| {
+                        options
+                            .iter()
+                            .find(|o| o.name == name)
+                            .and_then(|o| match &o.value {
+                                Some(CommandInteractionOptionValue::String(s)) => Some(s.clone()),
+                                _ => None,
+                            })
+                    };
+                    let prompt = option("prompt").ok_or("prompt is required")?;
+                    let size = option("size").unwrap_or(IMAGE_SIZES[0].to_string());
+                    put_command(
+                        dynamo_client,
+                        &env::var("DISCORD_COMMAND_TABLE")?,
+                        &DiscordCommand::imagine_command(
+                            &request.id,
+                            &channel_id,
+                            &request.token,
+                            &prompt,
+                            &size,
+                            now,
+                        )
+                        .with_user_id(user_id.clone()),
+                    )
+                    .await?;
+                    let response = InteractionResponse::new(5, Option::<String>::None);
+                    Ok(Response::builder()
+                        .status(200)
+                        .header("content-type", "application/json")
+                        .body(Body::from(serde_json::to_string(&response)?))
+                        .unwrap())
+                }
+                "Summarize" => {
+                    let Some(message) = data.target_message().cloned() else {
+                        let response = InteractionResponse::new(
+                            4,
+                            Some(
+                                InteractionMessage::new("The message is not available").ephemeral(),
+                            ),
+                        );
+                        return Ok(Response::builder()
+                            .status(200)
+                            .header("content-type", "application/json")
+                            .body(Body::from(serde_json::to_string(&response)?))
+                            .unwrap());
+                    };
+                    // The resolved message comes with the one it replies to, which keeps
+                    // this working where the channel can't be read
+                    let mut messages: Vec<Message> = message
+                        .referenced_message
+                        .clone()
+                        .map(|m| *m)
+                        .into_iter()
+                        .collect();
+                    messages.push(message);
+                    let message_ids = messages.iter().map(|m| m.id.clone()).collect();
+                    let mut command_messages = convert_messsages_to_chat_command_message(messages);
+                    command_messages.push(ChatCommandMessage::user("Summarize the messages above"));
+                    put_command(
+                        dynamo_client,
+                        &env::var("DISCORD_COMMAND_TABLE")?,
+                        &DiscordCommand::chat_command(
                             &request.id,
                             &channel_id,
                             &request.token,
                             topic,
                             command_messages,
                             now,
-                        ))?))
-                        .send()
-                        .await?;
+                        )
+                        .with_user_id(user_id.clone())
+                        .with_guild_id(request.guild_id.clone())
+                        .with_sources(message_ids)
+                        .with_persona(persona_name)
+                        .with_params(params)
+                        .with_user_installed(user_installed),
+                    )
+                    .await?;
                     let response = InteractionResponse::new(5, Option::<String>::None);
                     Ok(Response::builder()
                         .status(200)
@@ -194,15 +417,325 @@ async fn post_interactions_handler(
                         .body(Body::from(serde_json::to_string(&response)?))
                         .unwrap())
                 }
-                "chata" => {
-                    let mut messages = match channel.type_ {
-                        11u32 | 12u32 => {
-                            get_get_messages(http_client, &channel_id, None, Some(100))
-                                .await?
-                                .json::<Vec<Message>>()
-                                .await?
+                "Transcribe" => {
+                    let attachments: Vec<_> = data
+                        .target_message()
+                        .map(|message| message.attachments.clone())
+                        .unwrap_or_default()
+                        .into_iter()
+                        .filter(is_audio_attachment)
+                        .collect();
+                    if attachments.is_empty() {
+                        let response = InteractionResponse::new(
+                            4,
+                            Some(InteractionMessage::new("The message has no audio").ephemeral()),
+                        );
+                        return Ok(Response::builder()
+                            .status(200)
+                            .header("content-type", "application/json")
+                            .body(Body::from(serde_json::to_string(&response)?))
+                            .unwrap());
+                    }
+                    put_command(
+                        dynamo_client,
+                        &env::var("DISCORD_COMMAND_TABLE")?,
+                        &DiscordCommand::transcribe_command(
+                            &request.id,
+                            &channel_id,
+                            &request.token,
+                            attachments,
+                            now,
+                        )
+                        .with_user_id(user_id.clone()),
+                    )
+                    .await?;
+                    let response = InteractionResponse::new(5, Option::<String>::None);
+                    Ok(Response::builder()
+                        .status(200)
+                        .header("content-type", "application/json")
+                        .body(Body::from(serde_json::to_string(&response)?))
+                        .unwrap())
+                }
+                "persona" => {
+                    let here = place.describe();
+                    let sub_command = data
+                        .options
+                        .unwrap_or_default()
+                        .into_iter()
+                        .next()
+                        .ok_or("sub command is required")?;
+                    let options = sub_command.options.unwrap_or_default();
+                    let content = match (&persona_table, sub_command.name.as_str()) {
+                        (None, _) => "Personas are not enabled".to_string(),
+                        (Some(table_name), "set") => {
+                            let name = CommandInteractionOption::find_string(&options, "name")
+                                .ok_or("name is required")?;
+                            let name = name.trim();
+                            let prompt = CommandInteractionOption::find_string(&options, "prompt");
+                            let saved = match prompt {
+                                Some(prompt) => {
+                                    let scope = place.save_scope(
+                                        CommandInteractionOption::find_string(&options, "scope")
+                                            .as_deref()
+                                            != Some("channel"),
+                                    );
+                                    let persona =
+                                        Persona::new(scope, name.to_string(), prompt, now);
+                                    put_persona(dynamo_client, table_name, &persona).await?;
+                                    true
+                                }
+                                None => find_persona(dynamo_client, table_name, &place, name)
+                                    .await?
+                                    .is_some(),
+                            };
+                            if saved {
+                                put_active_persona(
+                                    dynamo_client,
+                                    table_name,
+                                    &ActivePersona::new(place.selection_scope(), name, now),
+                                )
+                                .await?;
+                                format!("Answering as **{name}** {here}")
+                            } else {
+                                format!("Unknown persona: {name}. Give a prompt to save it.")
+                            }
+                        }
+                        (Some(_), "show") => match (&persona, &topic) {
+                            (Some(resolved), _) => format!(
+                                "Answering as **{}**, set by the {}\n>>> {}",
+                                resolved.persona.name, resolved.source, resolved.persona.prompt
+                            ),
+                            (None, Some(topic)) => {
+                                format!("No persona is set, answering with the topic\n>>> {topic}")
+                            }
+                            (None, None) => format!(
+                                "No persona is set, answering with the default prompt\n>>> {}",
+                                DEFAULT_SYSTEM_PROMPT
+                            ),
+                        },
+                        (Some(table_name), "clear") => {
+                            if delete_active_persona(
+                                dynamo_client,
+                                table_name,
+                                &place.selection_scope(),
+                            )
+                            .await?
+                            {
+                                format!("Cleared the persona {here}")
+                            } else {
+                                format!("No persona is set {here}")
+                            }
+                        }
+                        (Some(table_name), "list") => {
+                            let mut lines = Vec::new();
+                            for (scope, label) in place.persona_scopes().iter() {
+                                for persona in
+                                    list_personas(dynamo_client, table_name, scope).await?
+                                {
+                                    let summary: String = persona.prompt.chars().take(80).collect();
+                                    lines
+                                        .push(format!("**{}** ({label}): {summary}", persona.name));
+                                }
+                            }
+                            if lines.is_empty() {
+                                "No personas are saved".to_string()
+                            } else {
+                                lines.join("\n")
+                            }
+                        }
+                        (Some(_), name) => format!("Unknown sub command: {name}"),
+                    };
+                    let content: String =
+                        content.chars().take(MESSAGE_CONTENT_MAX_LENGTH).collect();
+                    let response = InteractionResponse::new(
+                        4,
+                        Some(InteractionMessage::new(content).ephemeral()),
+                    );
+                    Ok(Response::builder()
+                        .status(200)
+                        .header("content-type", "application/json")
+                        .body(Body::from(serde_json::to_string(&response)?))
+                        .unwrap())
+                }
+                "defaults" => {
+                    let sub_command = data
+                        .options
+                        .unwrap_or_default()
+                        .into_iter()
+                        .next()
+                        .ok_or("sub command is required")?;
+                    let describe = |params: &GenerationParams| {
+                        params
+                            .entries()
+                            .iter()
+                            .map(|(key, value)| format!("{key}: {value}"))
+                            .collect::<Vec<_>>()
+                            .join("\n")
+                    };
+                    let owner = match &place {
+                        PersonaPlace::Channel(_) => "this server",
+                        PersonaPlace::User(_) => "you",
+                    };
+                    let scope = place.defaults_scope();
+                    let content =
+                        match (&persona_table, scope.as_deref(), sub_command.name.as_str()) {
+                            (None, _, _) => "Defaults are not enabled".to_string(),
+                            (_, None, _) => "Defaults can only be set in servers".to_string(),
+                            (Some(table_name), Some(scope), "set") => {
+                                match params_from_options(&sub_command.options.unwrap_or_default())
+                                {
+                                    Ok(params) => {
+                                        let current =
+                                            get_defaults(dynamo_client, table_name, scope)
+                                                .await?
+                                                .map(|defaults| defaults.params)
+                                                .unwrap_or_default();
+                                        let params = params.or(&current);
+                                        put_defaults(
+                                            dynamo_client,
+                                            table_name,
+                                            &Defaults::new(scope.to_string(), params.clone(), now),
+                                        )
+                                        .await?;
+                                        format!(
+                                            "Saved the defaults for {owner}\n{}",
+                                            describe(&params)
+                                        )
+                                    }
+                                    Err(reason) => format!("Invalid option: {reason}"),
+                                }
+                            }
+                            (Some(table_name), Some(scope), "show") => {
+                                match get_defaults(dynamo_client, table_name, scope).await? {
+                                    Some(defaults) if !defaults.params.entries().is_empty() => {
+                                        format!(
+                                            "Defaults for {owner}\n{}",
+                                            describe(&defaults.params)
+                                        )
+                                    }
+                                    _ => "No defaults are set".to_string(),
+                                }
+                            }
+                            (Some(table_name), Some(scope), "clear") => {
+                                if delete_defaults(dynamo_client, table_name, scope).await? {
+                                    format!("Cleared the defaults for {owner}")
+                                } else {
+                                    "No defaults are set".to_string()
+                                }
+                            }
+                            (_, _, name) => format!("Unknown sub command: {name}"),
+                        };
+                    let content: String =
+                        content.chars().take(MESSAGE_CONTENT_MAX_LENGTH).collect();
+                    let response = InteractionResponse::new(
+                        4,
+                        Some(InteractionMessage::new(content).ephemeral()),
+                    );
+                    Ok(Response::builder()
+                        .status(200)
+                        .header("content-type", "application/json")
+                        .body(Body::from(serde_json::to_string(&response)?))
+                        .unwrap())
+                }
+                "topic-check" => {
+                    let directives = &channel_topic.directives;
+                    let mut lines = vec![match &channel_topic.channel_id {
+                        Some(id) if channel_topic.is_forum_post() => {
+                            format!("Guidelines of <#{id}>")
+                        }
+                        Some(id) if *id != channel_id => format!("Topic of <#{id}>"),
+                        _ => "Topic of this channel".to_string(),
+                    }];
+                    if user_installed {
+                        lines.push(
+                            "The app is installed to you only here, so the messages of this \
+                             channel are not read"
+                                .to_string(),
+                        );
+                    }
+                    if let Some(title) = &channel_topic.post_title {
+                        lines.push(format!("Post: {title}"));
+                    }
+                    if !channel_topic.post_tags.is_empty() {
+                        lines.push(format!("Tags: {}", channel_topic.post_tags.join(", ")));
+                    }
+                    lines.push(format!("Model: {}", directives.params.model()));
+                    for (key, value) in directives.params.entries() {
+                        if key != "model" {
+                            lines.push(format!("{key}: {value}"));
                         }
-                        _ => {
+                    }
+                    if let Some(history) = directives.history {
+                        lines.push(format!("History: {history} messages"));
+                    }
+                    for warning in channel_topic.warnings.iter() {
+                        lines.push(format!("Ignored {warning}"));
+                    }
+                    if let Some(resolved) = &persona {
+                        lines.push(format!(
+                            "The prompt is replaced by the persona **{}**",
+                            resolved.persona.name
+                        ));
+                    }
+                    lines.push(match &channel_topic.prompt {
+                        Some(prompt) => format!("Prompt:\n>>> {prompt}"),
+                        None => {
+                            format!("No prompt, the default is used\n>>> {DEFAULT_SYSTEM_PROMPT}")
+                        }
+                    });
+                    let content: String = lines
+                        .join("\n")
+                        .chars()
+                        .take(MESSAGE_CONTENT_MAX_LENGTH)
+                        .collect();
+                    let response = InteractionResponse::new(
+                        4,
+                        Some(InteractionMessage::new(content).ephemeral()),
+                    );
+                    Ok(Response::builder()
+                        .status(200)
+                        .header("content-type", "application/json")
+                        .body(Body::from(serde_json::to_string(&response)?))
+                        .unwrap())
+                }
+                "stop" => {
+                    let table_name = env::var("DISCORD_COMMAND_TABLE")?;
+                    // Like the Stop button, commands stored before their author was
+                    // recorded can be stopped by anyone
+                    let commands: Vec<_> =
+                        find_running_commands(dynamo_client, &table_name, &channel_id)
+                            .await?
+                            .into_iter()
+                            .filter(|command| {
+                                command.user_id.is_none() || command.user_id == user_id
+                            })
+                            .collect();
+                    for command in commands.iter() {
+                        set_command_cancelled(dynamo_client, &table_name, &command.id).await?;
+                    }
+                    let content = match commands.len() {
+                        0 => "No answer is being generated".to_string(),
+                        n => format!("Stopped {n} answer(s)"),
+                    };
+                    let response = InteractionResponse::new(
+                        4,
+                        Some(InteractionMessage::new(content).ephemeral()),
+                    );
+                    Ok(Response::builder()
+                        .status(200)
+                        .header("content-type", "application/json")
+                        .body(Body::from(serde_json::to_string(&response)?))
+                        .unwrap())
+                }
+                "chata" => {
+                    let history = match channel.is_thread() {
+                        // The starter message of a forum post is its opening question
+                        true => HistoryRequest::new(&request.id, channel_topic.directives.history)
+                            .with_starter_channel_id(
+                                starter_channel_id(&channel, channel_topic.is_forum_post())
+                                    .map(str::to_string),
+                            ),
+                        false => {
                             let response = InteractionResponse::new(
                                 4,
                                 Some(InteractionMessage::new(
@@ -216,19 +749,24 @@ async fn post_interactions_handler(
                                 .unwrap());
                         }
                     };
-                    messages.reverse();
-                    let command_messages = convert_messsages_to_chat_command_message(messages);
                     let res = dynamo_client
                         .put_item()
                         .table_name(env::var("DISCORD_COMMAND_TABLE")?)
-                        .set_item(Some(serde_dynamo::to_item(DiscordCommand::chat_command(
-                            &request.id,
-                            &channel_id,
-                            &request.token,
-                            topic,
-                            command_messages,
-                            now,
-                        ))?))
+                        .set_item(Some(serde_dynamo::to_item(
+                            DiscordCommand::chat_command(
+                                &request.id,
+                                &channel_id,
+                                &request.token,
+                                topic,
+                                Vec::new(),
+                                now,
+                            )
+                            .with_user_id(user_id.clone())
+                            .with_guild_id(request.guild_id.clone())
+                            .with_history(Some(history))
+                            .with_persona(persona_name)
+                            .with_params(params),
+                        )?))
                         .send()
                         .await?;
                     let response = InteractionResponse::new(5, Option::<String>::None);
@@ -245,57 +783,236 @@ async fn post_interactions_handler(
                     .unwrap()),
             }
         }
+        3u32 => {
+            let user_id = request.invoker().map(|user| user.id.clone());
+            let data = request.data.unwrap();
+            let now = Utc::now().timestamp_millis();
+            let custom_id = data.custom_id.unwrap_or_default();
+            let (button, command_id) = if let Some(parsed) = AnswerButton::parse(&custom_id) {
+                parsed
+            } else {
+                return Ok(Response::builder()
+                    .status(400)
+                    .header("content-type", "application/json")
+                    .body(Body::from("Unsupported components"))
+                    .unwrap());
+            };
+            let table_name = env::var("DISCORD_COMMAND_TABLE")?;
+            let command = get_command(dynamo_client, &table_name, command_id).await?;
+            // Commands stored before their author was recorded can be used by anyone
+            let command = match command {
+                Some(command) if command.user_id.is_none() || command.user_id == user_id => {
+                    Ok(command)
+                }
+                Some(_) => Err("Only the author of the command can use these buttons"),
+                None => Err("This answer is no longer available"),
+            };
+            let command = match command {
+                Ok(command) => command,
+                Err(content) => {
+                    let response = InteractionResponse::new(
+                        4,
+                        Some(InteractionMessage::new(content).ephemeral()),
+                    );
+                    return Ok(Response::builder()
+                        .status(200)
+                        .header("content-type", "application/json")
+                        .body(Body::from(serde_json::to_string(&response)?))
+                        .unwrap());
+                }
+            };
+            match button {
+                AnswerButton::Stop => {
+                    let response =
+                        if set_command_cancelled(dynamo_client, &table_name, command_id).await? {
+                            InteractionResponse::new(6, None)
+                        } else {
+                            InteractionResponse::new(
+                                4,
+                                Some(
+                                    InteractionMessage::new("This answer is no longer available")
+                                        .ephemeral(),
+                                ),
+                            )
+                        };
+                    Ok(Response::builder()
+                        .status(200)
+                        .header("content-type", "application/json")
+                        .body(Body::from(serde_json::to_string(&response)?))
+                        .unwrap())
+                }
+                AnswerButton::Regenerate | AnswerButton::Continue => {
+                    let mut chat_command = match command.command_type.to_chat_command() {
+                        Some(chat_command) => chat_command,
+                        None => {
+                            let response = InteractionResponse::new(
+                                4,
+                                Some(
+                                    InteractionMessage::new("This answer is no longer available")
+                                        .ephemeral(),
+                                ),
+                            );
+                            return Ok(Response::builder()
+                                .status(200)
+                                .header("content-type", "application/json")
+                                .body(Body::from(serde_json::to_string(&response)?))
+                                .unwrap());
+                        }
+                    };
+                    if button == AnswerButton::Continue {
+                        // Answers rendered as embeds have no content
+                        let answer = request
+                            .message
+                            .and_then(|m| m.get_text())
+                            .unwrap_or_default();
+                        chat_command
+                            .messages
+                            .push(ChatCommandMessage::assistant(answer));
+                        chat_command
+                            .messages
+                            .push(ChatCommandMessage::user("Continue"));
+                    }
+                    put_command(
+                        dynamo_client,
+                        &table_name,
+                        &DiscordCommand::chat_command(
+                            &request.id,
+                            &chat_command.channel_id,
+                            &request.token,
+                            chat_command.topic,
+                            chat_command.messages,
+                            now,
+                        )
+                        .with_user_id(user_id)
+                        .with_guild_id(chat_command.guild_id)
+                        .with_sources(chat_command.source_message_ids)
+                        .with_history(chat_command.history)
+                        .with_persona(chat_command.persona)
+                        .with_params(chat_command.params)
+                        .with_user_installed(chat_command.user_installed),
+                    )
+                    .await?;
+                    let response = InteractionResponse::new(5, Option::<String>::None);
+                    Ok(Response::builder()
+                        .status(200)
+                        .header("content-type", "application/json")
+                        .body(Body::from(serde_json::to_string(&response)?))
+                        .unwrap())
+                }
+            }
+        }
+        5u32 => {
+            if request.data.as_ref().and_then(|d| d.custom_id.as_deref()) != Some("prompt") {
+                return Ok(Response::builder()
+                    .status(400)
+                    .header("content-type", "application/json")
+                    .body(Body::from("Unsupported modals"))
+                    .unwrap());
+            }
+            let user_id = request.invoker().map(|user| user.id.clone());
+            let user_installed = !request.has_channel_access();
+            let Some(channel_id) = request.channel_id().map(str::to_string) else {
+                let response = InteractionResponse::new(
+                    4,
+                    Some(InteractionMessage::new("This command can't be used here").ephemeral()),
+                );
+                return Ok(Response::builder()
+                    .status(200)
+                    .header("content-type", "application/json")
+                    .body(Body::from(serde_json::to_string(&response)?))
+                    .unwrap());
+            };
+            let (channel, channel_topic) = get_interaction_channel(http_client, &request).await?;
+            let place = PersonaPlace::of_interaction(&request, &channel)?;
+            let data = request.data.unwrap();
+            let now = Utc::now().timestamp_millis();
+            let components = data.components.unwrap_or_default();
+            let prompt = Component::find_value(&components, "prompt").unwrap_or_default();
+            let system = Component::find_value(&components, "system")
+                .map(str::trim)
+                .filter(|s| !s.is_empty());
+            let persona_table = env::var("PERSONA_TABLE").ok();
+            let params = resolve_params(
+                dynamo_client,
+                persona_table.as_deref(),
+                place.defaults_scope().as_deref(),
+                &channel_topic.directives.params,
+                GenerationParams::default(),
+            )
+            .await?;
+            let (topic, persona_name) = if let Some(system) = system {
+                (Some(system.to_string()), None)
+            } else {
+                let persona = match &persona_table {
+                    Some(table_name) => {
+                        resolve_persona(dynamo_client, table_name, &place, None).await?
+                    }
+                    None => None,
+                };
+                match persona {
+                    Some(resolved) => (
+                        channel_topic.with_post_context(Some(resolved.persona.prompt)),
+                        Some(resolved.persona.name),
+                    ),
+                    None => (
+                        channel_topic.with_post_context(channel_topic.prompt.clone()),
+                        None,
+                    ),
+                }
+            };
+            put_command(
+                dynamo_client,
+                &env::var("DISCORD_COMMAND_TABLE")?,
+                &DiscordCommand::chat_command(
+                    &request.id,
+                    &channel_id,
+                    &request.token,
+                    topic,
+                    vec![ChatCommandMessage::user(prompt)],
+                    now,
+                )
+                .with_user_id(user_id.clone())
+                .with_guild_id(request.guild_id.clone())
+                .with_persona(persona_name)
+                .with_params(params)
+                .with_user_installed(user_installed),
+            )
+            .await?;
+            let response = InteractionResponse::new(5, Option::<String>::None);
+            Ok(Response::builder()
+                .status(200)
+                .header("content-type", "application/json")
+                .body(Body::from(serde_json::to_string(&response)?))
+                .unwrap())
+        }
         _ => Ok(Response::new(Body::from("unsupported type"))),
     }
 }
 
-#[instrument(ret, err)]
-fn validate_request(req: &Request) -> Result<(), Error> {
-    let headers = req.headers();
-    let signature = headers
-        .get("X-Signature-Ed25519")
-        .ok_or("Header not found: X-Signature-Ed25519")?
-        .to_str()?;
-    let timestamp = headers
-        .get("X-Signature-Timestamp")
-        .ok_or("Header not found: X-Signature-Timestamp")?
-        .to_str()?;
-    let body: String = from_utf8(req.body())?.to_string();
-
-    let raw_public_str = DISCORD_BOT_PUBLIC_KEY.unwrap();
-    let public_key = PublicKey::from_bytes(&hex::decode(raw_public_str)?).unwrap();
-    let signature = Signature::from_str(signature)?;
-    let msg = format!("{timestamp}{body}");
+struct Service {
+    http_client: reqwest::Client,
+    dynamo_client: aws_sdk_dynamodb::Client,
+    verify_signature: VerifySignatureLayer,
+}
 
-    public_key
-        .verify_strict(msg.as_bytes(), &signature)
-        .map_err(|e| Error::from(e.to_string()))
+async fn interactions_handler(req: Request, service: &Service) -> Result<Response<Body>, Error> {
+    post_interactions_handler(&req, &service.http_client, &service.dynamo_client).await
 }
 
 /// This is the main body for the function.
 /// Write your code inside it.
 /// There are some code example in the following URLs:
 /// - https://github.com/awslabs/aws-lambda-rust-runtime/tree/main/examples
-async fn function_handler(
-    req: &Request,
-    http_client: &reqwest::Client,
-    dynamo_client: &aws_sdk_dynamodb::Client,
-) -> Result<Response<Body>, Error> {
-    // Extract some useful information from the request
-    if validate_request(&req).is_err() {
-        let resp = Response::builder()
-            .status(401)
-            .header("content-type", "text/html")
-            .body("invalid request signature".into())
-            .map_err(Box::new)?;
-        return Ok(resp);
-    }
-
+async fn function_handler(req: Request, service: &Service) -> Result<Response<Body>, Error> {
     match (req.method(), req.uri().path()) {
         // Serve some instructions at /
         (&Method::GET, "/") => get_response(&req),
         (&Method::POST, "/api/interactions") => {
-            post_interactions_handler(&req, http_client, dynamo_client).await
+            service
+                .verify_signature
+                .layer(ServiceFn::new(interactions_handler, service))
+                .oneshot(req)
+                .await
         }
         _ => {
             error!("{req:?}");
@@ -322,15 +1039,13 @@ async fn main() -> Result<(), Error> {
         .with_line_number(true)
         .init();
 
-    let http_client = Arc::new(reqwest::Client::new());
     let config = aws_config::load_from_env().await;
-    let dynamo_client = Arc::new(aws_sdk_dynamodb::Client::new(&config));
-    // Define a closure here that makes use of the shared client.
-    let handler_func_closure = move |event: Request| {
-        let http_client = http_client.clone();
-        let dynamo_client = dynamo_client.clone();
-        async move { function_handler(&event, &http_client, &dynamo_client).await }
+    let validator = Arc::new(SignatureValidator::from_env()?);
+    let svs = &Service {
+        http_client: reqwest::Client::new(),
+        dynamo_client: aws_sdk_dynamodb::Client::new(&config),
+        verify_signature: VerifySignatureLayer::new(validator),
     };
 
-    run(service_fn(handler_func_closure)).await
+    run(ServiceFn::new(function_handler, svs)).await
 }
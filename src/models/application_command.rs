@@ -11,6 +11,14 @@ pub struct ApplicationCommand {
     pub type_: u32,
     pub description: Option<String>,
     pub options: Option<Vec<ApplicationCommandOption>>,
+    // https://discord.com/developers/docs/resources/application#application-object-application-integration-types
+    // 0 for guild installs and 1 for user installs
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub integration_types: Option<Vec<u32>>,
+    // https://discord.com/developers/docs/interactions/receiving-and-responding#interaction-object-interaction-context-types
+    // 0 in guilds, 1 in DMs with the bot and 2 in other DMs and group DMs
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub contexts: Option<Vec<u32>>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -22,4 +30,18 @@ pub struct ApplicationCommandOption {
     pub required: Option<bool>,
     pub min_length: Option<u32>,
     pub max_value: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub choices: Option<Vec<ApplicationCommandOptionChoice>>,
+    // Options of a sub command
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub options: Option<Vec<ApplicationCommandOption>>,
+}
+
+/**
+ * https://discord.com/developers/docs/interactions/application-commands#application-command-object-application-command-option-choice-structure
+ */
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ApplicationCommandOptionChoice {
+    pub name: String,
+    pub value: String,
 }
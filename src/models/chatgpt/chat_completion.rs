@@ -1,34 +1,289 @@
 use serde::{Deserialize, Serialize};
 
-use crate::models::dynamo::discord_command::{ChatCommand, ChatCommandMessage};
+use crate::{
+    constants::{DEFAULT_CHAT_MODEL, DEFAULT_SYSTEM_PROMPT, IMAGE_PART_ESTIMATED_TOKENS},
+    models::dynamo::discord_command::{ChatCommand, ChatCommandMessage},
+};
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ChatCompletionMessage {
     pub role: String,
-    pub content: String,
+    pub content: ChatCompletionContent,
+    // Author of a user turn, of `[a-zA-Z0-9_-]` only
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    // Calls requested by the assistant
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tool_calls: Option<Vec<ChatCompletionToolCall>>,
+    // The call answered by a `tool` message
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tool_call_id: Option<String>,
 }
 
 impl ChatCompletionMessage {
-    pub fn system<S: Into<String>>(content: S) -> Self {
+    fn new<C: Into<ChatCompletionContent>>(role: &str, content: C) -> Self {
         Self {
-            role: "system".to_string(),
+            role: role.to_string(),
             content: content.into(),
+            name: None,
+            tool_calls: None,
+            tool_call_id: None,
         }
     }
+    pub fn system<S: Into<String>>(content: S) -> Self {
+        Self::new("system", content.into())
+    }
     pub fn assistant<S: Into<String>>(content: S) -> Self {
+        Self::new("assistant", content.into())
+    }
+    pub fn user<C: Into<ChatCompletionContent>>(content: C) -> Self {
+        Self::new("user", content)
+    }
+    /// Assistant turn requesting `tool_calls`, with the text streamed before them
+    pub fn assistant_tool_calls<S: Into<String>>(
+        content: S,
+        tool_calls: Vec<ChatCompletionToolCall>,
+    ) -> Self {
         Self {
-            role: "assistant".to_string(),
-            content: content.into(),
+            tool_calls: Some(tool_calls),
+            ..Self::new("assistant", content.into())
         }
     }
-    pub fn user<S: Into<String>>(content: S) -> Self {
+    /// Result of the call `tool_call_id`
+    pub fn tool<S: Into<String>>(tool_call_id: S, content: S) -> Self {
         Self {
-            role: "user".to_string(),
-            content: content.into(),
+            tool_call_id: Some(tool_call_id.into()),
+            ..Self::new("tool", content.into())
+        }
+    }
+}
+
+/**
+ * https://platform.openai.com/docs/api-reference/chat/create#chat-create-tools
+ */
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ChatCompletionTool {
+    #[serde(rename = "type")]
+    pub type_: String,
+    pub function: ChatCompletionFunction,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ChatCompletionFunction {
+    pub name: String,
+    pub description: String,
+    // JSON schema of the arguments
+    pub parameters: serde_json::Value,
+}
+
+impl ChatCompletionTool {
+    pub fn function<S: Into<String>>(
+        name: S,
+        description: S,
+        parameters: serde_json::Value,
+    ) -> Self {
+        Self {
+            type_: "function".to_string(),
+            function: ChatCompletionFunction {
+                name: name.into(),
+                description: description.into(),
+                parameters,
+            },
         }
     }
 }
 
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ChatCompletionToolCall {
+    pub id: String,
+    #[serde(rename = "type")]
+    pub type_: String,
+    pub function: ChatCompletionFunctionCall,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ChatCompletionFunctionCall {
+    pub name: String,
+    // JSON encoded arguments, which the model may get wrong
+    pub arguments: String,
+}
+
+/**
+ * https://platform.openai.com/docs/api-reference/chat/streaming#chat/streaming-choices
+ *
+ * Calls are streamed in pieces: the first delta of a call has its `id` and name, the
+ * following ones parts of the arguments.
+ */
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ChatCompletionToolCallDelta {
+    pub index: usize,
+    pub id: Option<String>,
+    pub function: Option<ChatCompletionFunctionCallDelta>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ChatCompletionFunctionCallDelta {
+    pub name: Option<String>,
+    pub arguments: Option<String>,
+}
+
+impl ChatCompletionToolCall {
+    /// Merges streamed pieces into the calls they belong to
+    pub fn merge_deltas(calls: &mut Vec<Self>, deltas: Vec<ChatCompletionToolCallDelta>) {
+        for delta in deltas {
+            if calls.len() <= delta.index {
+                calls.resize_with(delta.index + 1, || Self {
+                    type_: "function".to_string(),
+                    ..Self::default()
+                });
+            }
+            let call = &mut calls[delta.index];
+            if let Some(id) = delta.id {
+                call.id = id;
+            }
+            if let Some(function) = delta.function {
+                if let Some(name) = function.name {
+                    call.function.name.push_str(&name);
+                }
+                if let Some(arguments) = function.arguments {
+                    call.function.arguments.push_str(&arguments);
+                }
+            }
+        }
+    }
+}
+
+/**
+ * https://platform.openai.com/docs/api-reference/chat/create#chat-create-messages
+ *
+ * Plain text is sent as a string, which models without vision support accept too.
+ */
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum ChatCompletionContent {
+    Text(String),
+    Parts(Vec<ChatCompletionContentPart>),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ChatCompletionContentPart {
+    Text { text: String },
+    ImageUrl { image_url: ChatCompletionImageUrl },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ChatCompletionImageUrl {
+    // An http(s) url or a `data:` url with base64 data
+    pub url: String,
+}
+
+impl ChatCompletionContent {
+    /// Text parts joined by newlines
+    pub fn text(&self) -> String {
+        match self {
+            Self::Text(text) => text.clone(),
+            Self::Parts(parts) => parts
+                .iter()
+                .filter_map(|part| match part {
+                    ChatCompletionContentPart::Text { text } => Some(text.as_str()),
+                    ChatCompletionContentPart::ImageUrl { .. } => None,
+                })
+                .collect::<Vec<_>>()
+                .join("\n"),
+        }
+    }
+
+    /// Inserts `text` before the first text part
+    pub fn prepend_text(&mut self, text: &str) {
+        match self {
+            Self::Text(current) => current.insert_str(0, text),
+            Self::Parts(parts) => match parts.first_mut() {
+                Some(ChatCompletionContentPart::Text { text: current }) => {
+                    current.insert_str(0, text)
+                }
+                _ => parts.insert(
+                    0,
+                    ChatCompletionContentPart::Text {
+                        text: text.to_string(),
+                    },
+                ),
+            },
+        }
+    }
+
+    /// Appends `text` to the last text part
+    pub fn push_text(&mut self, text: &str) {
+        match self {
+            Self::Text(current) => current.push_str(text),
+            Self::Parts(parts) => match parts.last_mut() {
+                Some(ChatCompletionContentPart::Text { text: current }) => current.push_str(text),
+                _ => parts.push(ChatCompletionContentPart::Text {
+                    text: text.to_string(),
+                }),
+            },
+        }
+    }
+
+    pub fn push_image_url<S: Into<String>>(&mut self, url: S) {
+        let part = ChatCompletionContentPart::ImageUrl {
+            image_url: ChatCompletionImageUrl { url: url.into() },
+        };
+        match self {
+            Self::Text(text) => {
+                let text = ChatCompletionContentPart::Text {
+                    text: std::mem::take(text),
+                };
+                *self = Self::Parts(vec![text, part]);
+            }
+            Self::Parts(parts) => parts.push(part),
+        }
+    }
+
+    /// Appends the parts of `other`, separated by a newline
+    pub fn append(&mut self, other: Self) {
+        self.push_text("\n");
+        match other {
+            Self::Text(text) => self.push_text(&text),
+            Self::Parts(parts) => {
+                for part in parts {
+                    match part {
+                        ChatCompletionContentPart::Text { text } => self.push_text(&text),
+                        ChatCompletionContentPart::ImageUrl { image_url } => {
+                            self.push_image_url(image_url.url)
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    pub fn estimate_tokens(&self) -> u32 {
+        match self {
+            Self::Text(text) => estimate_tokens(text),
+            Self::Parts(parts) => parts
+                .iter()
+                .map(|part| match part {
+                    ChatCompletionContentPart::Text { text } => estimate_tokens(text),
+                    ChatCompletionContentPart::ImageUrl { .. } => IMAGE_PART_ESTIMATED_TOKENS,
+                })
+                .sum(),
+        }
+    }
+}
+
+impl From<String> for ChatCompletionContent {
+    fn from(value: String) -> Self {
+        Self::Text(value)
+    }
+}
+
+impl From<&str> for ChatCompletionContent {
+    fn from(value: &str) -> Self {
+        Self::Text(value.to_string())
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ChatCompletionChoice {
     pub index: u32,
@@ -36,24 +291,55 @@ pub struct ChatCompletionChoice {
     pub message: ChatCompletionMessage,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct ChatCompletionChunkDelta {
     pub role: Option<String>,
     pub content: Option<String>,
+    pub tool_calls: Option<Vec<ChatCompletionToolCallDelta>>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ChatCompletionChunkChoice {
+    #[serde(default)]
     pub index: u32,
+    #[serde(default)]
     pub delta: ChatCompletionChunkDelta,
     pub finish_reason: Option<String>,
+    // Azure OpenAI only
+    pub content_filter_results: Option<serde_json::Value>,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
 pub struct ChatCompletionUsage {
-    prompt_tokens: u32,
-    completion_tokens: u32,
-    total_tokens: u32,
+    pub prompt_tokens: u32,
+    pub completion_tokens: u32,
+    pub total_tokens: u32,
+}
+
+/// Rough token count of `text`. About 4 characters make a token in English text.
+pub fn estimate_tokens(text: &str) -> u32 {
+    (text.chars().count() as u32).div_ceil(4)
+}
+
+impl ChatCompletionUsage {
+    /// Adds the usage of another request, e.g. of the next step of a tool call loop
+    pub fn add(&mut self, other: &Self) {
+        self.prompt_tokens += other.prompt_tokens;
+        self.completion_tokens += other.completion_tokens;
+        self.total_tokens += other.total_tokens;
+    }
+
+    /// Rough usage of a stream that ended before the API reported it, e.g. when it
+    /// was stopped.
+    pub fn estimate(request: &ChatCompletionRequest, completion: &str) -> Self {
+        let prompt_tokens = request.estimate_prompt_tokens();
+        let completion_tokens = estimate_tokens(completion);
+        Self {
+            prompt_tokens,
+            completion_tokens,
+            total_tokens: prompt_tokens + completion_tokens,
+        }
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -66,50 +352,316 @@ pub struct ChatCompletionResponse {
     pub choices: Vec<ChatCompletionChoice>,
 }
 
+/**
+ * Azure OpenAI streams chunks with empty `id`/`model` and no `choices`
+ * (e.g. `prompt_filter_results`), so every field is defaulted.
+ */
 #[derive(Debug, Serialize, Deserialize)]
 pub struct ChatCompletionChunkResponse {
+    #[serde(default)]
     pub id: String,
+    #[serde(default)]
     pub object: String,
+    #[serde(default)]
     pub created: u32,
+    #[serde(default)]
     pub model: String,
+    #[serde(default)]
     pub choices: Vec<ChatCompletionChunkChoice>,
+    // Only in the last chunk, when requested with `stream_options.include_usage`
+    pub usage: Option<ChatCompletionUsage>,
+    // Azure OpenAI only
+    pub prompt_filter_results: Option<serde_json::Value>,
+}
+
+/// Content, tool calls and usage read from a batch of stream chunks
+#[derive(Debug, Clone, Default)]
+pub struct ChatCompletionDelta {
+    pub content: String,
+    pub tool_calls: Vec<ChatCompletionToolCallDelta>,
+    pub usage: Option<ChatCompletionUsage>,
 }
 
 impl ChatCompletionResponse {
     pub fn get_total_token_usage(&self) -> u32 {
         self.usage.total_tokens
     }
+
+    pub fn get_content(&self) -> Option<String> {
+        self.choices.first().map(|c| c.message.content.text())
+    }
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ChatCompletionRequest {
     pub model: String,
     pub messages: Vec<ChatCompletionMessage>,
     pub stream: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stream_options: Option<ChatCompletionStreamOptions>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tools: Option<Vec<ChatCompletionTool>>,
+    // `auto`, or `none` to make the model answer without calling tools
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tool_choice: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub temperature: Option<f32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub top_p: Option<f32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_tokens: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stop: Option<Vec<String>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub presence_penalty: Option<f32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub frequency_penalty: Option<f32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub seed: Option<i64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub response_format: Option<ChatCompletionResponseFormat>,
 }
 
-impl From<ChatCommand> for ChatCompletionRequest {
-    fn from(value: ChatCommand) -> Self {
-        let system_message = if let Some(t) = value.topic {
+/**
+ * https://platform.openai.com/docs/api-reference/chat/create#chat-create-response_format
+ */
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ChatCompletionResponseFormat {
+    // `text` or `json_object`
+    #[serde(rename = "type")]
+    pub type_: String,
+}
+
+/// Settings of a completion stored with its command, so answers are regenerated with the
+/// same settings. Unset ones keep the defaults of the API.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct GenerationParams {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub model: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub temperature: Option<f32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub top_p: Option<f32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_tokens: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stop: Option<Vec<String>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub presence_penalty: Option<f32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub frequency_penalty: Option<f32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub seed: Option<i64>,
+    // `text` or `json_object`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub response_format: Option<String>,
+}
+
+impl GenerationParams {
+    /// Names of the settings in topic directives and command options
+    pub const KEYS: [&'static str; 9] = [
+        "model",
+        "temperature",
+        "top_p",
+        "max_tokens",
+        "stop",
+        "presence_penalty",
+        "frequency_penalty",
+        "seed",
+        "response_format",
+    ];
+
+    /// The model answering with these settings
+    pub fn model(&self) -> &str {
+        self.model.as_deref().unwrap_or(DEFAULT_CHAT_MODEL)
+    }
+
+    /// Sets `key` from its text form, e.g. `temperature=0.2`. Stop sequences are comma
+    /// separated.
+    pub fn set(&mut self, key: &str, value: &str) -> Result<(), String> {
+        fn parse<T: std::str::FromStr>(key: &str, value: &str) -> Result<T, String> {
+            value
+                .parse()
+                .map_err(|_| format!("{key} is not a number: {value}"))
+        }
+        fn in_range(key: &str, value: f32, min: f32, max: f32) -> Result<f32, String> {
+            if (min..=max).contains(&value) {
+                Ok(value)
+            } else {
+                Err(format!("{key} is a number from {min} to {max}"))
+            }
+        }
+        match key {
+            "model" if value.is_empty() => return Err("model is empty".to_string()),
+            "model" => self.model = Some(value.to_string()),
+            "temperature" => self.temperature = Some(in_range(key, parse(key, value)?, 0.0, 2.0)?),
+            "top_p" => self.top_p = Some(in_range(key, parse(key, value)?, 0.0, 1.0)?),
+            "max_tokens" => match parse(key, value)? {
+                0 => return Err("max_tokens is at least 1".to_string()),
+                n => self.max_tokens = Some(n),
+            },
+            "stop" => {
+                let stop: Vec<String> = value
+                    .split(',')
+                    .filter(|s| !s.is_empty())
+                    .map(str::to_string)
+                    .collect();
+                // https://platform.openai.com/docs/api-reference/chat/create#chat-create-stop
+                if !(1..=4).contains(&stop.len()) {
+                    return Err("stop is 1 to 4 comma separated sequences".to_string());
+                }
+                self.stop = Some(stop);
+            }
+            "presence_penalty" => {
+                self.presence_penalty = Some(in_range(key, parse(key, value)?, -2.0, 2.0)?)
+            }
+            "frequency_penalty" => {
+                self.frequency_penalty = Some(in_range(key, parse(key, value)?, -2.0, 2.0)?)
+            }
+            "seed" => self.seed = Some(parse(key, value)?),
+            "response_format" => match value {
+                "text" | "json_object" => self.response_format = Some(value.to_string()),
+                "json" => self.response_format = Some("json_object".to_string()),
+                _ => return Err("response_format is text or json_object".to_string()),
+            },
+            _ => return Err(format!("unknown setting {key}")),
+        }
+        Ok(())
+    }
+
+    /// The settings of `self`, and those of `fallback` where `self` has none
+    pub fn or(self, fallback: &Self) -> Self {
+        Self {
+            model: self.model.or_else(|| fallback.model.clone()),
+            temperature: self.temperature.or(fallback.temperature),
+            top_p: self.top_p.or(fallback.top_p),
+            max_tokens: self.max_tokens.or(fallback.max_tokens),
+            stop: self.stop.or_else(|| fallback.stop.clone()),
+            presence_penalty: self.presence_penalty.or(fallback.presence_penalty),
+            frequency_penalty: self.frequency_penalty.or(fallback.frequency_penalty),
+            seed: self.seed.or(fallback.seed),
+            response_format: self
+                .response_format
+                .or_else(|| fallback.response_format.clone()),
+        }
+    }
+
+    /// The settings which are set, as `(key, text form)`
+    pub fn entries(&self) -> Vec<(&'static str, String)> {
+        let entries = [
+            ("model", self.model.clone()),
+            ("temperature", self.temperature.map(|v| v.to_string())),
+            ("top_p", self.top_p.map(|v| v.to_string())),
+            ("max_tokens", self.max_tokens.map(|v| v.to_string())),
+            ("stop", self.stop.as_ref().map(|v| v.join(","))),
+            (
+                "presence_penalty",
+                self.presence_penalty.map(|v| v.to_string()),
+            ),
+            (
+                "frequency_penalty",
+                self.frequency_penalty.map(|v| v.to_string()),
+            ),
+            ("seed", self.seed.map(|v| v.to_string())),
+            ("response_format", self.response_format.clone()),
+        ];
+        entries
+            .into_iter()
+            .filter_map(|(key, value)| Some((key, value?)))
+            .collect()
+    }
+}
+
+/**
+ * https://platform.openai.com/docs/api-reference/chat/create#chat-create-stream_options
+ */
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ChatCompletionStreamOptions {
+    pub include_usage: bool,
+}
+
+impl ChatCompletionRequest {
+    /// Streaming request for the conversation `messages` with `topic` as the system prompt
+    pub fn new(topic: Option<String>, messages: &[ChatCommandMessage]) -> Self {
+        let system_message = if let Some(t) = topic {
             ChatCompletionMessage::system(t)
         } else {
-            ChatCompletionMessage::system("You're concise")
+            ChatCompletionMessage::system(DEFAULT_SYSTEM_PROMPT)
         };
-        let mut messages = vec![system_message];
-        for msg in value.messages.iter() {
+        let mut completion_messages = vec![system_message];
+        for msg in messages.iter() {
             match msg {
-                ChatCommandMessage::User { content } => {
-                    messages.push(ChatCompletionMessage::user(content))
+                ChatCommandMessage::User { content, name, .. } => {
+                    completion_messages.push(ChatCompletionMessage {
+                        name: name.clone(),
+                        ..ChatCompletionMessage::user(content.clone())
+                    })
                 }
                 ChatCommandMessage::Assistant { content } => {
-                    messages.push(ChatCompletionMessage::assistant(content))
+                    completion_messages.push(ChatCompletionMessage::assistant(content))
                 }
             }
         }
         Self {
-            model: "gpt-3.5-turbo".to_string(),
-            messages,
+            model: DEFAULT_CHAT_MODEL.to_string(),
+            messages: completion_messages,
             stream: Some(true),
+            stream_options: Some(ChatCompletionStreamOptions {
+                include_usage: true,
+            }),
+            tools: None,
+            tool_choice: None,
+            temperature: None,
+            top_p: None,
+            max_tokens: None,
+            stop: None,
+            presence_penalty: None,
+            frequency_penalty: None,
+            seed: None,
+            response_format: None,
+        }
+    }
+
+    pub fn with_params(mut self, params: &GenerationParams) -> Self {
+        self.model = params.model().to_string();
+        self.temperature = params.temperature;
+        self.top_p = params.top_p;
+        self.max_tokens = params.max_tokens;
+        self.stop = params.stop.clone();
+        self.presence_penalty = params.presence_penalty;
+        self.frequency_penalty = params.frequency_penalty;
+        self.seed = params.seed;
+        self.response_format = params
+            .response_format
+            .clone()
+            .map(|type_| ChatCompletionResponseFormat { type_ });
+        self
+    }
+
+    pub fn estimate_prompt_tokens(&self) -> u32 {
+        self.messages
+            .iter()
+            .map(|m| {
+                let arguments = m.tool_calls.iter().flatten();
+                m.content.estimate_tokens()
+                    + arguments
+                        .map(|call| estimate_tokens(&call.function.arguments))
+                        .sum::<u32>()
+            })
+            .sum()
+    }
+
+    /// Drops the oldest turns after the system prompt until the prompt fits in `budget`
+    /// tokens. The latest turn is always kept.
+    pub fn fit_token_budget(&mut self, budget: u32) {
+        while self.messages.len() > 2 && self.estimate_prompt_tokens() > budget {
+            self.messages.remove(1);
         }
     }
 }
+
+impl From<ChatCommand> for ChatCompletionRequest {
+    fn from(value: ChatCommand) -> Self {
+        Self::new(value.topic, &value.messages).with_params(&value.params)
+    }
+}
@@ -0,0 +1,43 @@
+use serde::{Deserialize, Serialize};
+
+use crate::constants::DEFAULT_IMAGE_MODEL;
+
+/**
+ * https://platform.openai.com/docs/api-reference/images/create
+ */
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ImageGenerationRequest {
+    pub model: String,
+    pub prompt: String,
+    pub n: u32,
+    pub size: String,
+    // "url" or "b64_json"
+    pub response_format: String,
+}
+
+impl ImageGenerationRequest {
+    /// Request of a single image returned as base64, so it can be uploaded to Discord
+    pub fn new<S: Into<String>>(prompt: S, size: S) -> Self {
+        Self {
+            model: DEFAULT_IMAGE_MODEL.to_string(),
+            prompt: prompt.into(),
+            n: 1,
+            size: size.into(),
+            response_format: "b64_json".to_string(),
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ImageGenerationData {
+    pub b64_json: Option<String>,
+    pub url: Option<String>,
+    // The prompt rewritten by the model, dall-e-3 only
+    pub revised_prompt: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ImageGenerationResponse {
+    pub created: u64,
+    pub data: Vec<ImageGenerationData>,
+}
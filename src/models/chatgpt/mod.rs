@@ -1 +1,2 @@
 pub mod chat_completion;
+pub mod image_generation;
@@ -0,0 +1,31 @@
+use serde::{Deserialize, Serialize};
+
+/**
+ * https://discord.com/developers/docs/resources/channel#allowed-mentions-object
+ */
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct AllowedMentions {
+    // "roles", "users" and "everyone"
+    pub parse: Vec<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub roles: Vec<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub users: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub replied_user: Option<bool>,
+}
+
+impl AllowedMentions {
+    /// Nothing is pinged
+    pub fn none() -> Self {
+        Self::default()
+    }
+
+    /// Pings mention types, e.g. `users`
+    pub fn parse<S: Into<String>>(types: Vec<S>) -> Self {
+        Self {
+            parse: types.into_iter().map(Into::into).collect(),
+            ..Default::default()
+        }
+    }
+}
@@ -0,0 +1,40 @@
+use serde::{Deserialize, Serialize};
+
+/**
+ * https://discord.com/developers/docs/resources/channel#attachment-object
+ *
+ * Attachments sent with a message only need `id`, which refers to the index of the
+ * uploaded file, and optionally `filename` and `description`.
+ */
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Attachment {
+    pub id: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub filename: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub content_type: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub size: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub url: Option<String>,
+    // Length of voice messages
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub duration_secs: Option<f64>,
+}
+
+impl Attachment {
+    /// Attachment of the `index`th file uploaded with a message
+    pub fn upload<S: Into<String>>(index: usize, filename: S) -> Self {
+        Self {
+            id: index.to_string(),
+            filename: Some(filename.into()),
+            description: None,
+            content_type: None,
+            size: None,
+            url: None,
+            duration_secs: None,
+        }
+    }
+}
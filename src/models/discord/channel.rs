@@ -3,7 +3,7 @@ use serde::{Deserialize, Serialize};
 /**
  * https://discord.com/developers/docs/resources/channel#channels-resource
  */
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Channel {
     pub id: String,
     pub name: Option<String>,
@@ -18,4 +18,32 @@ pub struct Channel {
     pub member_count: Option<u32>,
     pub rate_limit_per_user: Option<u32>,
     pub total_message_sent: Option<u32>,
+    // Tags which can be applied to the posts of a forum or media channel
+    #[serde(default)]
+    pub available_tags: Vec<ForumTag>,
+    // Tag ids of a forum post
+    #[serde(default)]
+    pub applied_tags: Vec<String>,
+}
+
+/**
+ * https://discord.com/developers/docs/resources/channel#forum-tag-object
+ */
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ForumTag {
+    pub id: String,
+    pub name: String,
+    pub emoji_name: Option<String>,
+}
+
+impl Channel {
+    /// https://discord.com/developers/docs/resources/channel#channel-object-channel-types
+    pub fn is_thread(&self) -> bool {
+        matches!(self.type_, 10..=12)
+    }
+
+    /// Forum and media channels, whose threads are posts
+    pub fn is_forum(&self) -> bool {
+        matches!(self.type_, 15 | 16)
+    }
 }
@@ -0,0 +1,103 @@
+use serde::{Deserialize, Serialize};
+
+/**
+ * https://discord.com/developers/docs/interactions/message-components#component-object
+ */
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Component {
+    // https://discord.com/developers/docs/interactions/message-components#component-object-component-types
+    #[serde(rename = "type")]
+    pub type_: u32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub custom_id: Option<String>,
+    // https://discord.com/developers/docs/interactions/message-components#button-object-button-styles
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub style: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub label: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub disabled: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub components: Option<Vec<Component>>,
+    // Text inputs of modals
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub value: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub placeholder: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub required: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_length: Option<u32>,
+}
+
+impl Component {
+    pub fn action_row(components: Vec<Component>) -> Self {
+        Self {
+            type_: 1,
+            custom_id: None,
+            style: None,
+            label: None,
+            disabled: None,
+            components: Some(components),
+            value: None,
+            placeholder: None,
+            required: None,
+            max_length: None,
+        }
+    }
+
+    pub fn button<S: Into<String>>(style: u32, label: S, custom_id: S) -> Self {
+        Self {
+            type_: 2,
+            custom_id: Some(custom_id.into()),
+            style: Some(style),
+            label: Some(label.into()),
+            disabled: None,
+            components: None,
+            value: None,
+            placeholder: None,
+            required: None,
+            max_length: None,
+        }
+    }
+
+    pub fn disabled(mut self) -> Self {
+        self.disabled = Some(true);
+        self
+    }
+
+    /**
+     * https://discord.com/developers/docs/interactions/message-components#text-inputs
+     */
+    pub fn text_input<S: Into<String>>(
+        style: u32,
+        label: S,
+        custom_id: S,
+        required: bool,
+        max_length: u32,
+    ) -> Self {
+        Self {
+            type_: 4,
+            custom_id: Some(custom_id.into()),
+            style: Some(style),
+            label: Some(label.into()),
+            disabled: None,
+            components: None,
+            value: None,
+            placeholder: None,
+            required: Some(required),
+            max_length: Some(max_length),
+        }
+    }
+
+    /// Values of the text inputs in a submitted modal by their `custom_id`
+    pub fn find_value<'a>(components: &'a [Component], custom_id: &str) -> Option<&'a str> {
+        components.iter().find_map(|c| {
+            if c.custom_id.as_deref() == Some(custom_id) {
+                c.value.as_deref()
+            } else {
+                Self::find_value(c.components.as_deref().unwrap_or_default(), custom_id)
+            }
+        })
+    }
+}
@@ -0,0 +1,62 @@
+use serde::{Deserialize, Serialize};
+
+/**
+ * https://discord.com/developers/docs/resources/channel#embed-object
+ */
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Embed {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub title: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub url: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub color: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub footer: Option<EmbedFooter>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub fields: Vec<EmbedField>,
+}
+
+impl Embed {
+    pub fn new<S: Into<String>>(description: S) -> Self {
+        Self {
+            description: Some(description.into()),
+            ..Default::default()
+        }
+    }
+
+    pub fn with_footer<S: Into<String>>(mut self, text: S) -> Self {
+        self.footer = Some(EmbedFooter { text: text.into() });
+        self
+    }
+
+    pub fn with_field<S: Into<String>>(mut self, name: S, value: S) -> Self {
+        self.fields.push(EmbedField {
+            name: name.into(),
+            value: value.into(),
+            inline: None,
+        });
+        self
+    }
+}
+
+/**
+ * https://discord.com/developers/docs/resources/channel#embed-object-embed-footer-structure
+ */
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EmbedFooter {
+    pub text: String,
+}
+
+/**
+ * https://discord.com/developers/docs/resources/channel#embed-object-embed-field-structure
+ */
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EmbedField {
+    pub name: String,
+    pub value: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub inline: Option<bool>,
+}
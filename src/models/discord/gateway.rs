@@ -0,0 +1,79 @@
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use super::user::DiscordUser;
+
+/**
+ * https://discord.com/developers/docs/topics/gateway-events#payload-structure
+ */
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GatewayPayload {
+    // https://discord.com/developers/docs/topics/opcodes-and-status-codes#gateway-gateway-opcodes
+    pub op: u8,
+    pub d: Option<Value>,
+    pub s: Option<u64>,
+    pub t: Option<String>,
+}
+
+impl GatewayPayload {
+    pub fn new(op: u8, d: Value) -> Self {
+        Self {
+            op,
+            d: Some(d),
+            s: None,
+            t: None,
+        }
+    }
+}
+
+/**
+ * https://discord.com/developers/docs/topics/gateway-events#hello
+ */
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GatewayHello {
+    pub heartbeat_interval: u64,
+}
+
+/**
+ * https://discord.com/developers/docs/topics/gateway-events#identify
+ */
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GatewayIdentify {
+    pub token: String,
+    pub intents: u64,
+    pub properties: GatewayIdentifyProperties,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GatewayIdentifyProperties {
+    pub os: String,
+    pub browser: String,
+    pub device: String,
+}
+
+/**
+ * https://discord.com/developers/docs/topics/gateway-events#resume
+ */
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GatewayResume {
+    pub token: String,
+    pub session_id: String,
+    pub seq: u64,
+}
+
+/**
+ * https://discord.com/developers/docs/topics/gateway-events#ready
+ */
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GatewayReady {
+    pub session_id: String,
+    pub resume_gateway_url: String,
+    pub user: DiscordUser,
+}
+
+/// Dispatch (op 0) event, e.g. `MESSAGE_CREATE`
+#[derive(Debug, Clone)]
+pub struct GatewayDispatch {
+    pub name: String,
+    pub data: Value,
+}
@@ -1,6 +1,10 @@
 use serde::{Deserialize, Serialize};
 
-use super::user::DiscordUser;
+use super::{
+    attachment::Attachment,
+    embed::Embed,
+    user::{DiscordGuildMember, DiscordUser},
+};
 
 /**
  * https://discord.com/developers/docs/resources/channel#message-object
@@ -11,18 +15,94 @@ pub struct Message {
     // https://discord.com/developers/docs/resources/channel#message-object-message-types
     #[serde(rename = "type")]
     pub type_: u32,
+    pub channel_id: Option<String>,
     pub timestamp: String,
     pub content: Option<String>,
     pub author: DiscordUser,
+    // Only in gateway events of guild messages
+    pub member: Option<DiscordGuildMember>,
+    #[serde(default)]
+    pub mentions: Vec<DiscordUser>,
+    #[serde(default)]
+    pub embeds: Vec<Embed>,
+    #[serde(default)]
+    pub attachments: Vec<Attachment>,
+    // The message replied to. Only set one level deep, `message_reference` links the
+    // messages above it.
     pub referenced_message: Option<Box<Message>>,
+    pub message_reference: Option<MessageReference>,
+    // https://discord.com/developers/docs/resources/message#message-object-message-flags
+    #[serde(default)]
+    pub flags: u64,
+}
+
+/**
+ * https://discord.com/developers/docs/resources/message#message-reference-structure
+ */
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MessageReference {
+    // 0 for replies, 1 for forwarded messages
+    #[serde(rename = "type", default)]
+    pub type_: u32,
+    pub message_id: Option<String>,
+    pub channel_id: Option<String>,
+    pub guild_id: Option<String>,
 }
 
 impl Message {
-    pub fn get_message_content(&self) -> Option<String> {
-        if let Some(referenced_message) = self.referenced_message.clone() {
-            referenced_message.content.to_owned()
-        } else {
-            self.content.clone()
+    // The "is thinking..." placeholder of a deferred interaction response
+    pub const FLAG_LOADING: u64 = 1 << 7;
+
+    /// Messages of bots other than this one
+    pub fn is_other_bot(&self, application_id: &str) -> bool {
+        self.author.bot == Some(true) && self.author.id != application_id
+    }
+
+    /// Text of the message itself. Answers rendered as embeds have their text in the
+    /// description.
+    pub fn get_text(&self) -> Option<String> {
+        match self.content.as_deref() {
+            Some(content) if !content.is_empty() => Some(content.to_string()),
+            _ => self
+                .embeds
+                .iter()
+                .find_map(|embed| embed.description.clone())
+                .or_else(|| self.content.clone()),
+        }
+    }
+
+    /// Name of the author, preferring the nickname in the guild
+    pub fn author_name(&self) -> &str {
+        self.member
+            .as_ref()
+            .and_then(|member| member.nick.as_deref())
+            .unwrap_or(self.author.display_name())
+    }
+
+    /// Replaces the user mentions in `text`, e.g. `<@123>`, with `@` and the display name
+    /// of the user. Mentions of users not mentioned by this message or the one it replies
+    /// to are kept.
+    pub fn resolve_mentions(&self, text: &str) -> String {
+        let users = self.mentions.iter().chain(
+            self.referenced_message
+                .iter()
+                .flat_map(|message| message.mentions.iter()),
+        );
+        let mut text = text.to_string();
+        for user in users {
+            let name = format!("@{}", user.display_name());
+            text = text
+                .replace(&format!("<@{}>", user.id), &name)
+                .replace(&format!("<@!{}>", user.id), &name);
         }
+        text
+    }
+
+    /// Jump link of a message. Messages outside guilds use `@me`.
+    pub fn link(guild_id: Option<&str>, channel_id: &str, message_id: &str) -> String {
+        format!(
+            "https://discord.com/channels/{}/{channel_id}/{message_id}",
+            guild_id.unwrap_or("@me")
+        )
     }
 }
@@ -1,4 +1,9 @@
+pub mod allowed_mentions;
+pub mod attachment;
 pub mod channel;
+pub mod component;
+pub mod embed;
+pub mod gateway;
 pub mod message;
 pub mod request;
 pub mod response;
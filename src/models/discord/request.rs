@@ -1,29 +1,94 @@
+use std::{collections::HashMap, fmt};
+
 use serde::{Deserialize, Serialize};
 
-use super::user::{DiscordGuildMember, DiscordUser};
+use super::{
+    channel::Channel,
+    component::Component,
+    message::Message,
+    user::{DiscordGuildMember, DiscordUser},
+};
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(untagged)]
 pub enum CommandInteractionOptionValue {
     Int(i32),
+    // Numbers and integers out of the range of `i32`
+    Number(f64),
     String(String),
 }
 
+impl fmt::Display for CommandInteractionOptionValue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Int(i) => write!(f, "{i}"),
+            Self::Number(n) => write!(f, "{n}"),
+            Self::String(s) => write!(f, "{s}"),
+        }
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct CommandInteractionOption {
     pub name: String,
     #[serde(rename = "type")]
     pub type_: u32,
     pub value: Option<CommandInteractionOptionValue>,
+    // Options of a sub command
+    pub options: Option<Vec<CommandInteractionOption>>,
+}
+
+impl CommandInteractionOption {
+    /// Value of the string option `name` in `options`
+    pub fn find_string(options: &[Self], name: &str) -> Option<String> {
+        options
+            .iter()
+            .find(|o| o.name == name)
+            .and_then(|o| match &o.value {
+                Some(CommandInteractionOptionValue::String(s)) => Some(s.clone()),
+                _ => None,
+            })
+    }
 }
 
+/**
+ * https://discord.com/developers/docs/interactions/receiving-and-responding#interaction-object-interaction-data
+ *
+ * Command fields are empty for MESSAGE_COMPONENT and MODAL_SUBMIT interactions.
+ */
 #[derive(Debug, Serialize, Deserialize)]
 pub struct InteractionData {
+    #[serde(default)]
     pub id: String,
+    #[serde(default)]
     pub name: String,
-    #[serde(rename = "type")]
+    #[serde(rename = "type", default)]
     pub type_: u32,
     pub options: Option<Vec<CommandInteractionOption>>,
+    pub custom_id: Option<String>,
+    pub component_type: Option<u32>,
+    // Submitted text inputs of MODAL_SUBMIT
+    pub components: Option<Vec<Component>>,
+    // The message or user a context menu command is used on
+    pub target_id: Option<String>,
+    pub resolved: Option<ResolvedData>,
+}
+
+impl InteractionData {
+    /// The message a message command is used on
+    pub fn target_message(&self) -> Option<&Message> {
+        let target_id = self.target_id.as_ref()?;
+        self.resolved.as_ref()?.messages.get(target_id)
+    }
+}
+
+/**
+ * https://discord.com/developers/docs/interactions/receiving-and-responding#interaction-object-resolved-data-structure
+ */
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ResolvedData {
+    #[serde(default)]
+    pub messages: HashMap<String, Message>,
 }
 
 /**
@@ -35,8 +100,56 @@ pub struct InteractionRequest {
     pub token: String,
     #[serde(rename = "type")]
     pub type_: u32,
+    pub guild_id: Option<String>,
     pub channel_id: Option<String>,
     pub data: Option<InteractionData>,
+    // Set in DMs, `member` in guilds
     pub user: Option<DiscordUser>,
     pub member: Option<DiscordGuildMember>,
+    // The message a component is attached to
+    pub message: Option<Message>,
+    // Partial channel the interaction was sent from
+    pub channel: Option<Channel>,
+    // https://discord.com/developers/docs/interactions/receiving-and-responding#interaction-object-interaction-context-types
+    pub context: Option<u32>,
+    // Installations the interaction was authorized by, keyed by integration type: `0` to
+    // the guild id, or `0` in DMs with the bot, and `1` to the user id
+    #[serde(default)]
+    pub authorizing_integration_owners: HashMap<String, String>,
+}
+
+impl InteractionRequest {
+    /// The user who invoked the interaction
+    pub fn invoker(&self) -> Option<&DiscordUser> {
+        self.member
+            .as_ref()
+            .and_then(|member| member.user.as_ref())
+            .or(self.user.as_ref())
+    }
+
+    /// The channel the interaction was sent from. Only the partial `channel` has it in
+    /// some contexts.
+    pub fn channel_id(&self) -> Option<&str> {
+        self.channel_id
+            .as_deref()
+            .or(self.channel.as_ref().map(|channel| channel.id.as_str()))
+    }
+
+    /// Whether the app is installed to the guild of the interaction, rather than only to
+    /// the user. Interactions sent before user installs existed have no owners.
+    pub fn is_guild_installed(&self) -> bool {
+        match &self.guild_id {
+            Some(guild_id) => {
+                self.authorizing_integration_owners.is_empty()
+                    || self.authorizing_integration_owners.get("0") == Some(guild_id)
+            }
+            None => false,
+        }
+    }
+
+    /// Whether the bot can read the channel with its own token: in guilds it is installed
+    /// to and in its DMs
+    pub fn has_channel_access(&self) -> bool {
+        self.is_guild_installed() || self.context == Some(1)
+    }
 }
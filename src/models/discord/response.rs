@@ -1,9 +1,27 @@
 use serde::{Deserialize, Serialize};
 
+use super::{
+    allowed_mentions::AllowedMentions, attachment::Attachment, component::Component, embed::Embed,
+};
+
+/**
+ * https://discord.com/developers/docs/interactions/receiving-and-responding#interaction-response-object-messages
+ */
 #[derive(Serialize, Deserialize)]
 pub struct InteractionMessage {
     pub tts: Option<bool>,
     pub content: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub embeds: Option<Vec<Embed>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub components: Option<Vec<Component>>,
+    // https://discord.com/developers/docs/resources/channel#message-object-message-flags
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub flags: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub allowed_mentions: Option<AllowedMentions>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub attachments: Option<Vec<Attachment>>,
 }
 
 impl InteractionMessage {
@@ -11,8 +29,45 @@ impl InteractionMessage {
         Self {
             tts: None,
             content: Some(content.into()),
+            embeds: None,
+            components: None,
+            flags: None,
+            // Model output is never trusted to ping anyone
+            allowed_mentions: Some(AllowedMentions::none()),
+            attachments: None,
         }
     }
+
+    pub fn with_embeds(mut self, embeds: Vec<Embed>) -> Self {
+        self.embeds = Some(embeds);
+        self
+    }
+
+    pub fn with_components(mut self, components: Vec<Component>) -> Self {
+        self.components = Some(components);
+        self
+    }
+
+    pub fn with_allowed_mentions(mut self, allowed_mentions: AllowedMentions) -> Self {
+        self.allowed_mentions = Some(allowed_mentions);
+        self
+    }
+
+    /// Only shown to the user who invoked the interaction
+    pub fn ephemeral(mut self) -> Self {
+        self.flags = Some(1 << 6);
+        self
+    }
+}
+
+/**
+ * https://discord.com/developers/docs/interactions/receiving-and-responding#interaction-response-object-modal
+ */
+#[derive(Serialize, Deserialize)]
+pub struct InteractionModal {
+    pub custom_id: String,
+    pub title: String,
+    pub components: Vec<Component>,
 }
 
 /**
@@ -8,9 +8,19 @@ pub struct DiscordUser {
     pub id: String,
     pub username: String,
     pub discriminator: String,
+    pub global_name: Option<String>,
+    pub bot: Option<bool>,
+}
+
+impl DiscordUser {
+    /// The display name, or the username of users without one
+    pub fn display_name(&self) -> &str {
+        self.global_name.as_deref().unwrap_or(&self.username)
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct DiscordGuildMember {
     pub user: Option<DiscordUser>,
+    pub nick: Option<String>,
 }
@@ -1,7 +1,65 @@
 use serde::{Deserialize, Serialize};
 
+use super::{
+    allowed_mentions::AllowedMentions, attachment::Attachment, component::Component, embed::Embed,
+};
+
+/**
+ * https://discord.com/developers/docs/resources/webhook#execute-webhook-jsonform-params
+ *
+ * Also used to create and edit messages of the bot, which take the same fields.
+ */
 #[derive(Debug, Serialize, Deserialize)]
 pub struct WebhookRequest {
     pub content: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub embeds: Option<Vec<Embed>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub components: Option<Vec<Component>>,
+    // https://discord.com/developers/docs/resources/channel#message-object-message-flags
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub flags: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub allowed_mentions: Option<AllowedMentions>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub attachments: Option<Vec<Attachment>>,
 }
 
+impl WebhookRequest {
+    pub fn new<S: Into<String>>(content: S) -> Self {
+        Self {
+            content: content.into(),
+            embeds: None,
+            components: None,
+            flags: None,
+            // Model output is never trusted to ping anyone
+            allowed_mentions: Some(AllowedMentions::none()),
+            attachments: None,
+        }
+    }
+
+    pub fn with_embeds(mut self, embeds: Vec<Embed>) -> Self {
+        self.embeds = Some(embeds);
+        self
+    }
+
+    pub fn with_components(mut self, components: Vec<Component>) -> Self {
+        self.components = Some(components);
+        self
+    }
+
+    pub fn with_flags(mut self, flags: u32) -> Self {
+        self.flags = Some(flags);
+        self
+    }
+
+    pub fn with_allowed_mentions(mut self, allowed_mentions: AllowedMentions) -> Self {
+        self.allowed_mentions = Some(allowed_mentions);
+        self
+    }
+
+    pub fn with_attachments(mut self, attachments: Vec<Attachment>) -> Self {
+        self.attachments = Some(attachments);
+        self
+    }
+}
@@ -0,0 +1,29 @@
+use serde::{Deserialize, Serialize};
+
+use crate::models::chatgpt::chat_completion::GenerationParams;
+
+/// Generation settings of a guild, or of a user where the app is installed to the user
+/// only, used where neither the command nor the channel topic sets them. Saved in the
+/// persona table under the guild or user scope.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct Defaults {
+    pub scope: String,
+    // Always `defaults`
+    pub key: String,
+    pub params: GenerationParams,
+    pub updated_at: i64,
+}
+
+impl Defaults {
+    pub const KEY: &'static str = "defaults";
+
+    pub fn new(scope: String, params: GenerationParams, now: i64) -> Self {
+        Self {
+            scope,
+            key: Self::KEY.to_string(),
+            params,
+            updated_at: now,
+        }
+    }
+}
@@ -1,11 +1,33 @@
 use serde::{Deserialize, Serialize};
 
+use crate::models::{
+    chatgpt::chat_completion::{ChatCompletionContent, ChatCompletionUsage, GenerationParams},
+    discord::{attachment::Attachment, message::Message},
+};
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(rename_all = "PascalCase")]
 pub struct DiscordCommand {
     pub id: String,
     #[serde(flatten)]
     pub command_type: CommandType,
+    // Copy of the channel of the command, the key of the channel index
+    #[serde(default)]
+    pub channel_id: String,
+    // The thread a `/ask` command is answered in, recorded by the worker
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub thread_id: Option<String>,
+    // Set by the Stop button or `/stop`. Cancelled commands are never processed again.
+    #[serde(default)]
+    pub cancelled: bool,
+    // Tokens used by the answer, recorded by the worker with `CompletedAt`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub usage: Option<ChatCompletionUsage>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub completed_at: Option<i64>,
+    // The user who invoked the command, the only one who can use the buttons of its answer
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub user_id: Option<String>,
     pub created_at: i64,
     pub updated_at: i64,
 }
@@ -22,14 +44,171 @@ impl DiscordCommand {
     where
         S: Into<String>,
     {
+        let channel_id = channel_id.into();
         Self {
             id: id.into(),
             command_type: CommandType::Chat(ChatCommand::new(
-                channel_id,
-                interaction_token,
+                channel_id.clone(),
+                interaction_token.into(),
                 topic,
                 messages,
             )),
+            channel_id,
+            thread_id: None,
+            cancelled: false,
+            usage: None,
+            completed_at: None,
+            user_id: None,
+            created_at: now,
+            updated_at: now,
+        }
+    }
+
+    pub fn with_user_id(mut self, user_id: Option<String>) -> Self {
+        self.user_id = user_id;
+        self
+    }
+
+    pub fn with_guild_id(mut self, guild_id: Option<String>) -> Self {
+        match &mut self.command_type {
+            CommandType::Chat(chat_command) => chat_command.guild_id = guild_id,
+            CommandType::Ask(ask_command) => ask_command.guild_id = guild_id,
+            CommandType::Imagine(_) | CommandType::Transcribe(_) => {}
+        }
+        self
+    }
+
+    /// Name of the persona whose prompt is the `topic`, shown with the answer
+    pub fn with_persona(mut self, persona: Option<String>) -> Self {
+        match &mut self.command_type {
+            CommandType::Chat(chat_command) => chat_command.persona = persona,
+            CommandType::Ask(ask_command) => ask_command.persona = persona,
+            CommandType::Imagine(_) | CommandType::Transcribe(_) => {}
+        }
+        self
+    }
+
+    pub fn with_params(mut self, params: GenerationParams) -> Self {
+        match &mut self.command_type {
+            CommandType::Chat(chat_command) => chat_command.params = params,
+            CommandType::Ask(ask_command) => ask_command.params = params,
+            CommandType::Imagine(_) | CommandType::Transcribe(_) => {}
+        }
+        self
+    }
+
+    /// Marks a chat command answered where the bot can't read the channel
+    pub fn with_user_installed(mut self, user_installed: bool) -> Self {
+        if let CommandType::Chat(chat_command) = &mut self.command_type {
+            chat_command.user_installed = user_installed;
+        }
+        self
+    }
+
+    /// Leaves reading the history of the channel to the worker. Only chat commands read
+    /// history.
+    pub fn with_history(mut self, history: Option<HistoryRequest>) -> Self {
+        if let CommandType::Chat(chat_command) = &mut self.command_type {
+            chat_command.history = history;
+        }
+        self
+    }
+
+    /// Links the answer to the messages the conversation was read from. Only chat
+    /// commands have sources.
+    pub fn with_sources(mut self, message_ids: Vec<String>) -> Self {
+        if let CommandType::Chat(chat_command) = &mut self.command_type {
+            chat_command.source_message_ids = message_ids;
+        }
+        self
+    }
+
+    pub fn imagine_command<S>(
+        id: S,
+        channel_id: S,
+        interaction_token: S,
+        prompt: S,
+        size: S,
+        now: i64,
+    ) -> Self
+    where
+        S: Into<String>,
+    {
+        let channel_id = channel_id.into();
+        Self {
+            id: id.into(),
+            command_type: CommandType::Imagine(ImagineCommand {
+                channel_id: channel_id.clone(),
+                interaction_token: interaction_token.into(),
+                prompt: prompt.into(),
+                size: size.into(),
+            }),
+            channel_id,
+            thread_id: None,
+            cancelled: false,
+            usage: None,
+            completed_at: None,
+            user_id: None,
+            created_at: now,
+            updated_at: now,
+        }
+    }
+
+    pub fn transcribe_command<S>(
+        id: S,
+        channel_id: S,
+        interaction_token: S,
+        attachments: Vec<Attachment>,
+        now: i64,
+    ) -> Self
+    where
+        S: Into<String>,
+    {
+        let channel_id = channel_id.into();
+        Self {
+            id: id.into(),
+            command_type: CommandType::Transcribe(TranscribeCommand {
+                channel_id: channel_id.clone(),
+                interaction_token: interaction_token.into(),
+                attachments,
+            }),
+            channel_id,
+            thread_id: None,
+            cancelled: false,
+            usage: None,
+            completed_at: None,
+            user_id: None,
+            created_at: now,
+            updated_at: now,
+        }
+    }
+
+    pub fn ask_command<S>(
+        id: S,
+        channel_id: S,
+        interaction_token: S,
+        topic: Option<String>,
+        question: S,
+        now: i64,
+    ) -> Self
+    where
+        S: Into<String>,
+    {
+        let channel_id = channel_id.into();
+        Self {
+            id: id.into(),
+            command_type: CommandType::Ask(AskCommand::new(
+                channel_id.clone(),
+                interaction_token.into(),
+                topic,
+                question.into(),
+            )),
+            channel_id,
+            thread_id: None,
+            cancelled: false,
+            usage: None,
+            completed_at: None,
+            user_id: None,
             created_at: now,
             updated_at: now,
         }
@@ -40,12 +219,50 @@ impl DiscordCommand {
 #[serde(rename_all = "PascalCase", tag = "CommandType", content = "Command")]
 pub enum CommandType {
     Chat(ChatCommand),
+    Ask(AskCommand),
+    Imagine(ImagineCommand),
+    Transcribe(TranscribeCommand),
+}
+
+impl CommandType {
+    /// The conversation of the command, e.g. to regenerate its answer. Images and
+    /// transcripts have no conversation.
+    pub fn to_chat_command(&self) -> Option<ChatCommand> {
+        let chat_command = match self {
+            Self::Chat(chat_command) => chat_command.clone(),
+            Self::Ask(ask_command) => ChatCommand {
+                guild_id: ask_command.guild_id.clone(),
+                persona: ask_command.persona.clone(),
+                params: ask_command.params.clone(),
+                ..ChatCommand::new(
+                    ask_command.channel_id.clone(),
+                    ask_command.interaction_token.clone(),
+                    ask_command.topic.clone(),
+                    vec![ChatCommandMessage::user(ask_command.question.clone())],
+                )
+            },
+            Self::Imagine(_) | Self::Transcribe(_) => return None,
+        };
+        Some(chat_command)
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum ChatCommandMessage {
-    User { content: String },
-    Assistant { content: String },
+    User {
+        // Text, or text and image parts. Items stored before images were supported
+        // have plain text.
+        content: ChatCompletionContent,
+        // Downloaded and inlined into `content` by the worker
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        attachments: Vec<Attachment>,
+        // Display name of the author
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        name: Option<String>,
+    },
+    Assistant {
+        content: String,
+    },
 }
 
 impl ChatCommandMessage {
@@ -56,7 +273,9 @@ impl ChatCommandMessage {
     }
     pub fn user<S: Into<String>>(content: S) -> Self {
         Self::User {
-            content: content.into(),
+            content: ChatCompletionContent::Text(content.into()),
+            attachments: Vec::new(),
+            name: None,
         }
     }
 }
@@ -67,6 +286,22 @@ pub struct ChatCommand {
     pub interaction_token: String,
     pub topic: Option<String>,
     pub messages: Vec<ChatCommandMessage>,
+    #[serde(default)]
+    pub guild_id: Option<String>,
+    // Messages in `channel_id` the conversation was read from
+    #[serde(default)]
+    pub source_message_ids: Vec<String>,
+    #[serde(default)]
+    pub persona: Option<String>,
+    #[serde(default)]
+    pub params: GenerationParams,
+    // Answered where the app is installed to the user only, so the bot can't read
+    // `channel_id` and tools reading it are left out
+    #[serde(default)]
+    pub user_installed: bool,
+    // Read by the worker and put in front of `messages`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub history: Option<HistoryRequest>,
 }
 
 impl ChatCommand {
@@ -81,6 +316,101 @@ impl ChatCommand {
             interaction_token: interaction_token.into(),
             topic,
             messages,
+            guild_id: None,
+            source_message_ids: Vec::new(),
+            persona: None,
+            params: GenerationParams::default(),
+            user_installed: false,
+            history: None,
+        }
+    }
+
+    /// Jump links of the source messages
+    pub fn source_links(&self) -> Vec<String> {
+        self.source_message_ids
+            .iter()
+            .map(|id| Message::link(self.guild_id.as_deref(), &self.channel_id, id))
+            .collect()
+    }
+}
+
+/// Messages of the channel of a chat command, read by the worker so the interaction is
+/// deferred without waiting for Discord
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HistoryRequest {
+    // Messages are read from the newest one before `before`, the id of the interaction,
+    // so answers read the channel as it was when the command was used
+    pub before: String,
+    // All messages within the limits of the worker when unset
+    pub max_messages: Option<u32>,
+    // The channel keeping the message a thread was started from, which is put first
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub starter_channel_id: Option<String>,
+}
+
+impl HistoryRequest {
+    pub fn new<S: Into<String>>(before: S, max_messages: Option<u32>) -> Self {
+        Self {
+            before: before.into(),
+            max_messages,
+            starter_channel_id: None,
         }
     }
+
+    pub fn with_starter_channel_id(mut self, starter_channel_id: Option<String>) -> Self {
+        self.starter_channel_id = starter_channel_id;
+        self
+    }
+}
+
+/// Answers `question` in a new thread started in `channel_id`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AskCommand {
+    pub channel_id: String,
+    pub interaction_token: String,
+    pub topic: Option<String>,
+    pub question: String,
+    #[serde(default)]
+    pub guild_id: Option<String>,
+    #[serde(default)]
+    pub persona: Option<String>,
+    #[serde(default)]
+    pub params: GenerationParams,
+}
+
+impl AskCommand {
+    pub fn new<S: Into<String>>(
+        channel_id: S,
+        interaction_token: S,
+        topic: Option<String>,
+        question: S,
+    ) -> Self {
+        Self {
+            channel_id: channel_id.into(),
+            interaction_token: interaction_token.into(),
+            topic,
+            question: question.into(),
+            guild_id: None,
+            persona: None,
+            params: GenerationParams::default(),
+        }
+    }
+}
+
+/// Generates an image of `prompt` and uploads it as a followup
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ImagineCommand {
+    pub channel_id: String,
+    pub interaction_token: String,
+    pub prompt: String,
+    // e.g. "1024x1024"
+    pub size: String,
+}
+
+/// Transcribes the audio attachments of a message and replies with the transcript
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TranscribeCommand {
+    pub channel_id: String,
+    pub interaction_token: String,
+    pub attachments: Vec<Attachment>,
 }
@@ -1 +1,3 @@
+pub mod defaults;
 pub mod discord_command;
+pub mod persona;
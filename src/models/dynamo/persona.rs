@@ -0,0 +1,69 @@
+use serde::{Deserialize, Serialize};
+
+/// Partition of the personas saved in a guild
+pub fn guild_scope(guild_id: &str) -> String {
+    format!("guild#{guild_id}")
+}
+
+/// Partition of the personas saved in a channel or thread, and of its selection
+pub fn channel_scope(channel_id: &str) -> String {
+    format!("channel#{channel_id}")
+}
+
+/// Partition of the settings of a user, used where the app is installed to the user only
+pub fn user_scope(user_id: &str) -> String {
+    format!("user#{user_id}")
+}
+
+/// Named system prompt, saved in a guild, a channel or for a user
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct Persona {
+    pub scope: String,
+    // `persona#{name}`
+    pub key: String,
+    pub name: String,
+    pub prompt: String,
+    pub updated_at: i64,
+}
+
+impl Persona {
+    pub fn new<S: Into<String>>(scope: S, name: S, prompt: S, now: i64) -> Self {
+        let name = name.into();
+        Self {
+            scope: scope.into(),
+            key: Self::key(&name),
+            name,
+            prompt: prompt.into(),
+            updated_at: now,
+        }
+    }
+
+    pub fn key(name: &str) -> String {
+        format!("persona#{name}")
+    }
+}
+
+/// The persona answering in a channel or thread, or for a user
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct ActivePersona {
+    pub scope: String,
+    // Always `active`
+    pub key: String,
+    pub name: String,
+    pub updated_at: i64,
+}
+
+impl ActivePersona {
+    pub const KEY: &'static str = "active";
+
+    pub fn new<S: Into<String>>(scope: String, name: S, now: i64) -> Self {
+        Self {
+            scope,
+            key: Self::KEY.to_string(),
+            name: name.into(),
+            updated_at: now,
+        }
+    }
+}
@@ -0,0 +1,208 @@
+use base64::{engine::general_purpose::STANDARD, Engine};
+use futures_util::StreamExt;
+use tracing::{info, instrument, warn};
+
+use crate::{
+    constants::{IMAGE_ATTACHMENT_MAX_BYTES, IMAGE_PART_ESTIMATED_TOKENS},
+    error::Error,
+    models::{
+        chatgpt::chat_completion::estimate_tokens, discord::attachment::Attachment,
+        dynamo::discord_command::ChatCommandMessage,
+    },
+    services::{
+        discord_service::get_attachment,
+        transcription_service::{is_audio_attachment, transcribe_attachment, Transcriber},
+    },
+};
+
+/// How images are passed to vision models
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ImageInput {
+    /// The CDN url of the attachment, fetched by the provider
+    Url,
+    /// The downloaded image as a `data:` url, for providers which can't reach the CDN
+    Base64,
+}
+
+// https://platform.openai.com/docs/guides/vision
+const IMAGE_CONTENT_TYPES: [&str; 4] = ["image/png", "image/jpeg", "image/gif", "image/webp"];
+
+const TEXT_CONTENT_TYPES: [&str; 8] = [
+    "application/json",
+    "application/xml",
+    "application/yaml",
+    "application/x-yaml",
+    "application/toml",
+    "application/javascript",
+    "application/x-sh",
+    "application/sql",
+];
+
+const TEXT_EXTENSIONS: [&str; 36] = [
+    "txt", "md", "log", "csv", "tsv", "json", "jsonl", "yaml", "yml", "toml", "ini", "cfg", "conf",
+    "xml", "html", "css", "rs", "py", "js", "ts", "jsx", "tsx", "go", "java", "kt", "c", "h",
+    "cpp", "hpp", "cs", "rb", "php", "sh", "sql", "diff", "patch",
+];
+
+/// Attachments with a `text/*` or known text MIME type, or a known text extension
+pub fn is_text_attachment(attachment: &Attachment) -> bool {
+    let content_type = attachment
+        .content_type
+        .as_deref()
+        .and_then(|c| c.split(';').next())
+        .map(str::trim);
+    let extension = attachment
+        .filename
+        .as_deref()
+        .and_then(|f| f.rsplit_once('.'))
+        .map(|(_, ext)| ext.to_ascii_lowercase());
+    content_type.is_some_and(|c| c.starts_with("text/") || TEXT_CONTENT_TYPES.contains(&c))
+        || extension.is_some_and(|ext| TEXT_EXTENSIONS.contains(&ext.as_str()))
+}
+
+pub fn is_image_attachment(attachment: &Attachment) -> bool {
+    attachment
+        .content_type
+        .as_deref()
+        .is_some_and(|c| IMAGE_CONTENT_TYPES.contains(&c))
+}
+
+/// Downloads an image attachment as a base64 `data:` url
+#[instrument(skip(client, attachment), fields(filename = ?attachment.filename), err)]
+pub async fn download_image_data_url(
+    client: &reqwest::Client,
+    attachment: &Attachment,
+) -> Result<String, Error> {
+    if attachment.size.unwrap_or_default() > IMAGE_ATTACHMENT_MAX_BYTES {
+        return Err("image is too large".into());
+    }
+    let url = attachment.url.as_deref().ok_or("attachment without url")?;
+    let response = get_attachment(client, url).await?;
+    if !response.status().is_success() {
+        return Err(format!("failed to download attachment: {}", response.status()).into());
+    }
+    let content_type = attachment.content_type.as_deref().unwrap_or("image/png");
+    let bytes = response.bytes().await?;
+    Ok(format!(
+        "data:{content_type};base64,{}",
+        STANDARD.encode(bytes)
+    ))
+}
+
+/// Downloads a text attachment, reading at most `max_bytes`. Returns whether the text
+/// was cut.
+#[instrument(skip(client, attachment), fields(filename = ?attachment.filename), err)]
+pub async fn download_text_attachment(
+    client: &reqwest::Client,
+    attachment: &Attachment,
+    max_bytes: u64,
+) -> Result<(String, bool), Error> {
+    let url = attachment.url.as_deref().ok_or("attachment without url")?;
+    let response = get_attachment(client, url).await?;
+    if !response.status().is_success() {
+        return Err(format!("failed to download attachment: {}", response.status()).into());
+    }
+    let mut bytes_stream = response.bytes_stream();
+    let mut bytes = Vec::new();
+    let mut truncated = false;
+    while let Some(chunk) = bytes_stream.next().await {
+        bytes.extend_from_slice(&chunk?);
+        if bytes.len() as u64 > max_bytes {
+            bytes.truncate(max_bytes as usize);
+            truncated = true;
+            break;
+        }
+    }
+    // A cut may split a character, which is replaced
+    Ok((String::from_utf8_lossy(&bytes).into_owned(), truncated))
+}
+
+/// Appends the text attachments of user turns to their content under a filename header,
+/// and image attachments as image parts when `images` is set. Otherwise, e.g. for
+/// text-only models, images are skipped. Audio attachments are replaced with their
+/// transcript when a `transcriber` is set. Attachments are skipped once they would use
+/// more than `token_budget` tokens in total.
+pub async fn inline_attachments(
+    client: &reqwest::Client,
+    messages: &mut [ChatCommandMessage],
+    max_bytes: u64,
+    token_budget: u32,
+    images: Option<ImageInput>,
+    transcriber: Option<&dyn Transcriber>,
+) -> Result<(), Error> {
+    let mut used_tokens = 0;
+    for message in messages.iter_mut() {
+        let ChatCommandMessage::User {
+            content,
+            attachments,
+            ..
+        } = message
+        else {
+            continue;
+        };
+        for attachment in attachments.drain(..) {
+            let filename = attachment.filename.clone().unwrap_or_default();
+            if is_image_attachment(&attachment) {
+                let url = match images {
+                    Some(ImageInput::Url) => attachment.url.clone(),
+                    Some(ImageInput::Base64) => {
+                        match download_image_data_url(client, &attachment).await {
+                            Ok(url) => Some(url),
+                            Err(err) => {
+                                warn!("failed to read image {filename}: {err:?}");
+                                None
+                            }
+                        }
+                    }
+                    None => None,
+                };
+                if let Some(url) = url {
+                    if used_tokens + IMAGE_PART_ESTIMATED_TOKENS <= token_budget {
+                        used_tokens += IMAGE_PART_ESTIMATED_TOKENS;
+                        content.push_image_url(url);
+                    }
+                }
+                continue;
+            }
+            let section = if is_audio_attachment(&attachment) {
+                let Some(transcriber) = transcriber else {
+                    info!("skip audio attachment: {filename}");
+                    continue;
+                };
+                match transcribe_attachment(client, transcriber, &attachment).await {
+                    Ok(text) => format!("\n\n--- {filename} (transcript) ---\n{text}"),
+                    Err(err) => {
+                        warn!("failed to transcribe {filename}: {err:?}");
+                        continue;
+                    }
+                }
+            } else if is_text_attachment(&attachment) {
+                let (text, truncated) =
+                    match download_text_attachment(client, &attachment, max_bytes).await {
+                        Ok(downloaded) => downloaded,
+                        Err(err) => {
+                            warn!("failed to read attachment {filename}: {err:?}");
+                            continue;
+                        }
+                    };
+                let mut section = format!("\n\n--- {filename} ---\n{text}");
+                if truncated {
+                    section.push_str("\n[truncated]");
+                }
+                section
+            } else {
+                info!("skip unsupported attachment: {filename}");
+                continue;
+            };
+            let tokens = estimate_tokens(&section);
+            if used_tokens + tokens > token_budget {
+                info!("attachment exceeds the token budget: {filename}");
+                content.push_text(&format!("\n\n--- {filename} ---\n[omitted, too long]"));
+                continue;
+            }
+            used_tokens += tokens;
+            content.push_text(&section);
+        }
+    }
+    Ok(())
+}
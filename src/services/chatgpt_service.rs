@@ -1,79 +1,209 @@
 use std::str::from_utf8;
 
 use reqwest::Response;
-use tracing::{error, instrument};
+use tracing::{error, instrument, warn};
 
 use crate::{
-    endpoint::chatgpt_completions_endpoint,
-    environment::CHATGPT_API_KEY,
+    constants::{AZURE_OPENAI_DEFAULT_API_VERSION, DEFAULT_CHAT_MODEL, DEFAULT_VISION_MODELS},
+    endpoint::{
+        azure_chat_completions_endpoint, azure_image_generations_endpoint,
+        chatgpt_completions_endpoint, chatgpt_image_generations_endpoint,
+    },
+    environment::{
+        AZURE_OPENAI_API_KEY, AZURE_OPENAI_API_VERSION, AZURE_OPENAI_DEPLOYMENTS,
+        AZURE_OPENAI_ENDPOINT, CHATGPT_API_KEY,
+    },
     error::Error,
-    models::chatgpt::chat_completion::{ChatCompletionChunkResponse, ChatCompletionRequest},
+    models::chatgpt::{
+        chat_completion::{
+            ChatCompletionChunkResponse, ChatCompletionDelta, ChatCompletionMessage,
+            ChatCompletionRequest, ChatCompletionResponse,
+        },
+        image_generation::ImageGenerationRequest,
+    },
 };
 
 use futures_util::{Stream, StreamExt};
 
+/// Resolves the Azure deployment name for `model` from a comma separated list of
+/// `model=deployment` pairs. Models without a mapping are assumed to be deployed
+/// under their own name.
+pub fn azure_deployment_name(deployments: Option<&str>, model: &str) -> String {
+    deployments
+        .unwrap_or_default()
+        .split(',')
+        .filter_map(|pair| pair.split_once('='))
+        .find(|(m, _)| m.trim() == model)
+        .map(|(_, deployment)| deployment.trim().to_string())
+        .unwrap_or_else(|| model.to_string())
+}
+
+/// Whether `model` accepts image parts, from a comma separated list of vision models
+pub fn supports_vision(vision_models: Option<&str>, model: &str) -> bool {
+    vision_models
+        .unwrap_or(DEFAULT_VISION_MODELS)
+        .split(',')
+        .any(|m| m.trim() == model)
+}
+
 /**
- * https://discord.com/developers/docs/resources/channel#get-channel
+ * https://platform.openai.com/docs/api-reference/chat/create
+ *
+ * Requests are sent to Azure OpenAI instead when `AZURE_OPENAI_ENDPOINT` is set.
  */
 #[instrument(skip(client), ret, err)]
 pub async fn post_chat_completions(
     client: &reqwest::Client,
     request: &ChatCompletionRequest,
 ) -> Result<Response, Error> {
-    let resp = client
-        .post(chatgpt_completions_endpoint())
-        .header(
+    let builder = if let Some(azure_endpoint) = AZURE_OPENAI_ENDPOINT {
+        let deployment = azure_deployment_name(AZURE_OPENAI_DEPLOYMENTS, &request.model);
+        client
+            .post(azure_chat_completions_endpoint(
+                azure_endpoint,
+                &deployment,
+                AZURE_OPENAI_API_VERSION.unwrap_or(AZURE_OPENAI_DEFAULT_API_VERSION),
+            ))
+            .header("api-key", AZURE_OPENAI_API_KEY.unwrap())
+    } else {
+        client.post(chatgpt_completions_endpoint()).header(
+            "Authorization",
+            format!("Bearer {}", CHATGPT_API_KEY.unwrap()),
+        )
+    };
+    let resp = builder.json(request).send().await?;
+
+    Ok(resp)
+}
+
+/**
+ * https://platform.openai.com/docs/api-reference/images/create
+ *
+ * Requests are sent to Azure OpenAI instead when `AZURE_OPENAI_ENDPOINT` is set.
+ */
+#[instrument(skip(client), ret, err)]
+pub async fn post_image_generations(
+    client: &reqwest::Client,
+    request: &ImageGenerationRequest,
+) -> Result<Response, Error> {
+    let builder = if let Some(azure_endpoint) = AZURE_OPENAI_ENDPOINT {
+        let deployment = azure_deployment_name(AZURE_OPENAI_DEPLOYMENTS, &request.model);
+        client
+            .post(azure_image_generations_endpoint(
+                azure_endpoint,
+                &deployment,
+                AZURE_OPENAI_API_VERSION.unwrap_or(AZURE_OPENAI_DEFAULT_API_VERSION),
+            ))
+            .header("api-key", AZURE_OPENAI_API_KEY.unwrap())
+    } else {
+        client.post(chatgpt_image_generations_endpoint()).header(
             "Authorization",
             format!("Bearer {}", CHATGPT_API_KEY.unwrap()),
         )
-        .json(request)
-        .send()
-        .await?;
+    };
+    let resp = builder.json(request).send().await?;
 
     Ok(resp)
 }
 
+/// Asks the model for a short title of `text`, e.g. for thread names.
+#[instrument(skip(client), ret, err)]
+pub async fn generate_title(client: &reqwest::Client, text: &str) -> Result<String, Error> {
+    let request = ChatCompletionRequest {
+        model: DEFAULT_CHAT_MODEL.to_string(),
+        messages: vec![
+            ChatCompletionMessage::system(
+                "Write a title of at most 6 words for the user's message. Reply with the title only.",
+            ),
+            ChatCompletionMessage::user(text),
+        ],
+        stream: Some(false),
+        stream_options: None,
+        tools: None,
+        tool_choice: None,
+        temperature: None,
+        top_p: None,
+        max_tokens: None,
+        stop: None,
+        presence_penalty: None,
+        frequency_penalty: None,
+        seed: None,
+        response_format: None,
+    };
+    let response = post_chat_completions(client, &request).await?;
+    if !response.status().is_success() {
+        return Err(response.text().await?.into());
+    }
+    let response = response.json::<ChatCompletionResponse>().await?;
+    let title = response.get_content().ok_or("no title is generated")?;
+    Ok(title.trim().trim_matches('"').to_string())
+}
+
+/// The chunk of a `data:` line of the event stream. Other lines, e.g. blank lines between
+/// events, and the final `[DONE]` have none.
+fn parse_event_line(line: &str) -> Option<ChatCompletionChunkResponse> {
+    let data = line.trim().strip_prefix("data:")?.trim();
+    if data.is_empty() || data == "[DONE]" {
+        return None;
+    }
+    match serde_json::from_str(data) {
+        Ok(chunk) => Some(chunk),
+        Err(err) => {
+            error!("invalid chunk {data:?}: {err}");
+            None
+        }
+    }
+}
+
 #[instrument(skip(response))]
 pub fn response_extract_stream(
     response: Response,
     count: usize,
-) -> impl Stream<Item = Result<String, Error>> {
+) -> impl Stream<Item = Result<ChatCompletionDelta, Error>> {
     let mut bytes_stream = response.bytes_stream();
     async_stream::try_stream! {
         let mut stream_buffer: Vec<ChatCompletionChunkResponse> = vec![];
         let yield_buffer = |stream_buffer: Vec<ChatCompletionChunkResponse>| {
-                let mut yield_string = String::new();
+                let mut delta = ChatCompletionDelta::default();
                 for chunk in stream_buffer.iter() {
+                    if chunk.usage.is_some() {
+                        delta.usage = chunk.usage.clone();
+                    }
+                    // Azure sends chunks without choices, e.g. prompt filter results
                     if let Some(choice) = chunk.choices.first() {
                         if let Some(content) = choice.delta.clone().content {
-                            yield_string.push_str(&content.to_string());
+                            delta.content.push_str(&content);
+                        }
+                        if let Some(tool_calls) = choice.delta.clone().tool_calls {
+                            delta.tool_calls.extend(tool_calls);
+                        }
+                        if choice.finish_reason.as_deref() == Some("content_filter") {
+                            warn!(
+                                "completion stopped by content filter: {:?}",
+                                choice.content_filter_results
+                            );
                         }
                     }
                 }
-                yield_string
+                delta
         };
-        while let Some(item) = bytes_stream.next().await {
-            let bytes = item?;
-            let str_bytes = from_utf8(&bytes[..])?;
-            if str_bytes.len() < 6 {
-                continue;
-            }
-            let trim_start = str_bytes.split("data: ");
-            let mut buf = "".to_string();
-            for spl in trim_start {
-                if spl.len() == 0 || spl == "[DONE]\n\n" {
-                    if buf.len() > 0 {
-                        error!("chunk buffer: {buf:?}");
-                    }
-                    buf = "".to_string();
-                    continue;
+        // Reads may end inside a line or a multibyte character, so only complete lines
+        // are parsed and the rest is kept for the next read
+        let mut pending: Vec<u8> = Vec::new();
+        let mut finished = false;
+        while !finished {
+            match bytes_stream.next().await {
+                Some(item) => pending.extend_from_slice(&item?),
+                // The last line may come without a line break
+                None => {
+                    finished = true;
+                    pending.push(b'\n');
                 }
-                buf.push_str(spl);
-                let chunk: Result<ChatCompletionChunkResponse, _> =
-                    serde_json::from_str(&buf);
-                if let Ok(chunk) = chunk {
+            }
+            while let Some(end) = pending.iter().position(|b| *b == b'\n') {
+                let line: Vec<u8> = pending.drain(..=end).collect();
+                if let Some(chunk) = parse_event_line(from_utf8(&line)?) {
                     stream_buffer.push(chunk);
-                    buf = "".to_string();
                 }
             }
             if stream_buffer.len() > count {
@@ -86,3 +216,87 @@ pub fn response_extract_stream(
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use futures_util::{pin_mut, stream};
+    use lambda_http::http;
+
+    use super::*;
+
+    /// A streamed response whose body is read in `reads`
+    fn response(reads: Vec<Vec<u8>>) -> Response {
+        let reads = stream::iter(reads.into_iter().map(Ok::<_, std::io::Error>));
+        Response::from(http::Response::new(reqwest::Body::wrap_stream(reads)))
+    }
+
+    async fn collect(response: Response) -> ChatCompletionDelta {
+        let stream = response_extract_stream(response, 0);
+        pin_mut!(stream);
+        let mut collected = ChatCompletionDelta::default();
+        while let Some(delta) = stream.next().await {
+            let delta = delta.unwrap();
+            collected.content.push_str(&delta.content);
+            collected.tool_calls.extend(delta.tool_calls);
+            if delta.usage.is_some() {
+                collected.usage = delta.usage;
+            }
+        }
+        collected
+    }
+
+    fn content_line(content: &str) -> String {
+        let chunk = serde_json::json!({ "choices": [{ "delta": { "content": content } }] });
+        format!("data: {chunk}\n\n")
+    }
+
+    #[tokio::test]
+    async fn joins_lines_split_across_reads() {
+        let body = format!(
+            "{}{}data: [DONE]\n\n",
+            content_line("Hello, "),
+            content_line("wörld")
+        );
+        let bytes = body.into_bytes();
+        // Every read ends inside a line, and one inside the two bytes of "ö"
+        let split = bytes.windows(2).position(|w| w == "ö".as_bytes()).unwrap() + 1;
+        let reads = vec![
+            bytes[..10].to_vec(),
+            bytes[10..split].to_vec(),
+            bytes[split..].to_vec(),
+        ];
+        assert_eq!(collect(response(reads)).await.content, "Hello, wörld");
+    }
+
+    #[tokio::test]
+    async fn keeps_every_tool_call_fragment() {
+        let fragments = ["{\"expr", "ession\": ", "\"1 + 1\"}"];
+        let body: String = fragments
+            .iter()
+            .map(|arguments| {
+                let chunk = serde_json::json!({
+                    "choices": [{ "delta": { "tool_calls": [
+                        { "index": 0, "function": { "arguments": arguments } }
+                    ] } }]
+                });
+                format!("data: {chunk}\n\n")
+            })
+            .collect();
+        // One byte per read
+        let reads = body.into_bytes().into_iter().map(|b| vec![b]).collect();
+        let arguments: String = collect(response(reads))
+            .await
+            .tool_calls
+            .iter()
+            .filter_map(|call| call.function.as_ref()?.arguments.clone())
+            .collect();
+        assert_eq!(arguments, "{\"expression\": \"1 + 1\"}");
+    }
+
+    #[tokio::test]
+    async fn parses_last_line_without_line_break() {
+        let body = content_line("done");
+        let reads = vec![body.trim_end().as_bytes().to_vec()];
+        assert_eq!(collect(response(reads)).await.content, "done");
+    }
+}
@@ -0,0 +1,325 @@
+use std::{collections::HashSet, env, sync::Arc};
+
+use tracing::{instrument, warn};
+
+use crate::{
+    constants::{
+        DEFAULT_ATTACHMENT_MAX_BYTES, DEFAULT_HISTORY_MESSAGE_TYPES, DEFAULT_PROMPT_TOKEN_BUDGET,
+        DEFAULT_REPLY_CHAIN_MAX_DEPTH, DEFAULT_SPEAKER_PREFIX_FORMAT,
+    },
+    environment::DISCORD_APPLICATION_ID,
+    error::Error,
+    models::{
+        chatgpt::chat_completion::{ChatCompletionRequest, GenerationParams},
+        discord::message::Message,
+        dynamo::discord_command::ChatCommandMessage,
+    },
+    services::{
+        attachment_service::{inline_attachments, ImageInput},
+        chatgpt_service::supports_vision,
+        discord_service::get_get_message,
+        transcription_service::{transcriber_from_env, Transcriber},
+    },
+};
+
+/// How the authors of user turns are told to the model
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SpeakerAttribution {
+    /// The `name` field of the turn. Names with other characters than `[a-zA-Z0-9_-]`
+    /// are given as a prefix instead.
+    Name,
+    /// Text before the content, with `{name}` replaced by the name
+    Prefix(String),
+    None,
+}
+
+impl SpeakerAttribution {
+    /// Set by `SPEAKER_ATTRIBUTION`, `name` by default, `prefix` or `none`. The prefix is
+    /// `SPEAKER_PREFIX_FORMAT`.
+    pub fn from_env() -> Result<Self, Error> {
+        let prefix_format =
+            env::var("SPEAKER_PREFIX_FORMAT").unwrap_or(DEFAULT_SPEAKER_PREFIX_FORMAT.to_string());
+        match env::var("SPEAKER_ATTRIBUTION").as_deref() {
+            Ok("name") | Err(_) => Ok(Self::Name),
+            Ok("prefix") => Ok(Self::Prefix(prefix_format)),
+            Ok("none") => Ok(Self::None),
+            Ok(attribution) => Err(format!("unknown SPEAKER_ATTRIBUTION: {attribution}").into()),
+        }
+    }
+
+    /// Moves the names of user turns into their content, or drops them, as set
+    fn apply(&self, messages: &mut [ChatCommandMessage]) {
+        for message in messages.iter_mut() {
+            let ChatCommandMessage::User { content, name, .. } = message else {
+                continue;
+            };
+            let Some(speaker) = name.take() else {
+                continue;
+            };
+            let prefix_format = match self {
+                Self::Name if is_valid_name(&speaker) => {
+                    *name = Some(speaker);
+                    continue;
+                }
+                Self::Name => DEFAULT_SPEAKER_PREFIX_FORMAT,
+                Self::Prefix(prefix_format) => prefix_format,
+                Self::None => continue,
+            };
+            content.prepend_text(&prefix_format.replace("{name}", &speaker));
+        }
+    }
+}
+
+/// https://platform.openai.com/docs/api-reference/chat/create#chat-create-messages
+fn is_valid_name(name: &str) -> bool {
+    (1..=64).contains(&name.len())
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+}
+
+/// How prompts are built, set by `PROMPT_TOKEN_BUDGET`, `ATTACHMENT_MAX_BYTES`,
+/// `VISION_MODELS`, `VISION_IMAGE_INPUT` (`url` or `base64`), the `SPEAKER_*` and the
+/// `TRANSCRIPTION_*` variables
+#[derive(Debug, Clone)]
+pub struct PromptOptions {
+    /// Estimated tokens of the whole prompt, including inlined attachments
+    pub token_budget: u32,
+    /// Bytes read from each text attachment
+    pub attachment_max_bytes: u64,
+    /// Comma separated models accepting images
+    pub vision_models: Option<String>,
+    pub image_input: ImageInput,
+    /// Transcribes audio attachments, which are skipped without one
+    pub transcriber: Option<Arc<dyn Transcriber>>,
+    pub speakers: SpeakerAttribution,
+}
+
+impl PromptOptions {
+    pub fn from_env() -> Result<Self, Error> {
+        let token_budget = match env::var("PROMPT_TOKEN_BUDGET") {
+            Ok(budget) => budget.parse()?,
+            Err(_) => DEFAULT_PROMPT_TOKEN_BUDGET,
+        };
+        let attachment_max_bytes = match env::var("ATTACHMENT_MAX_BYTES") {
+            Ok(bytes) => bytes.parse()?,
+            Err(_) => DEFAULT_ATTACHMENT_MAX_BYTES,
+        };
+        let image_input = match env::var("VISION_IMAGE_INPUT").as_deref() {
+            Ok("url") | Err(_) => ImageInput::Url,
+            Ok("base64") => ImageInput::Base64,
+            Ok(input) => return Err(format!("unknown VISION_IMAGE_INPUT: {input}").into()),
+        };
+        Ok(Self {
+            token_budget,
+            attachment_max_bytes,
+            vision_models: env::var("VISION_MODELS").ok(),
+            image_input,
+            transcriber: transcriber_from_env()?,
+            speakers: SpeakerAttribution::from_env()?,
+        })
+    }
+}
+
+/// Builds the completion request of a conversation with its attachments inlined,
+/// dropping the oldest turns once the prompt exceeds the token budget. Images are only
+/// sent to vision models.
+#[instrument(skip(client, messages), err)]
+pub async fn build_chat_request(
+    client: &reqwest::Client,
+    topic: Option<String>,
+    mut messages: Vec<ChatCommandMessage>,
+    params: &GenerationParams,
+    options: &PromptOptions,
+) -> Result<ChatCompletionRequest, Error> {
+    let images = supports_vision(options.vision_models.as_deref(), params.model())
+        .then_some(options.image_input);
+    inline_attachments(
+        client,
+        &mut messages,
+        options.attachment_max_bytes,
+        options.token_budget,
+        images,
+        options.transcriber.as_deref(),
+    )
+    .await?;
+    options.speakers.apply(&mut messages);
+    let mut request = ChatCompletionRequest::new(topic, &messages).with_params(params);
+    request.fit_token_budget(options.token_budget);
+    Ok(request)
+}
+
+/// Which messages of the history become conversation turns, set by
+/// `HISTORY_MESSAGE_TYPES`, `HISTORY_OTHER_BOTS` (`exclude` or `label`) and
+/// `HISTORY_IGNORE_PREFIXES`. Messages without text or attachments and the "is thinking..."
+/// placeholders of the bot are always dropped.
+#[derive(Debug, Clone)]
+pub struct HistoryFilter {
+    /// Comma separated message types, only conversational ones by default
+    pub message_types: Vec<u32>,
+    /// Keep the messages of other bots, which are labelled as such
+    pub label_other_bots: bool,
+    /// Comma separated prefixes of messages to leave out, e.g. `!` for other bots' commands
+    pub ignore_prefixes: Vec<String>,
+}
+
+impl HistoryFilter {
+    pub fn from_env() -> Result<Self, Error> {
+        let message_types = match env::var("HISTORY_MESSAGE_TYPES") {
+            Ok(types) => types
+                .split(',')
+                .map(|t| t.trim().parse())
+                .collect::<Result<_, _>>()?,
+            Err(_) => DEFAULT_HISTORY_MESSAGE_TYPES.to_vec(),
+        };
+        let label_other_bots = match env::var("HISTORY_OTHER_BOTS").as_deref() {
+            Ok("exclude") | Err(_) => false,
+            Ok("label") => true,
+            Ok(other_bots) => {
+                return Err(format!("unknown HISTORY_OTHER_BOTS: {other_bots}").into())
+            }
+        };
+        let ignore_prefixes = env::var("HISTORY_IGNORE_PREFIXES")
+            .unwrap_or_default()
+            .split(',')
+            .map(str::trim)
+            .filter(|prefix| !prefix.is_empty())
+            .map(str::to_string)
+            .collect();
+        Ok(Self {
+            message_types,
+            label_other_bots,
+            ignore_prefixes,
+        })
+    }
+
+    pub fn keep(&self, message: &Message) -> bool {
+        if !self.message_types.contains(&message.type_)
+            || message.flags & Message::FLAG_LOADING != 0
+            || (!self.label_other_bots && message.is_other_bot(DISCORD_APPLICATION_ID.unwrap()))
+        {
+            return false;
+        }
+        let text = message.get_text().unwrap_or_default();
+        let text = text.trim();
+        (!text.is_empty() || !message.attachments.is_empty())
+            && !self
+                .ignore_prefixes
+                .iter()
+                .any(|prefix| text.starts_with(prefix.as_str()))
+    }
+
+    /// Drops the messages which are not kept
+    pub fn apply(&self, messages: &mut Vec<Message>) {
+        messages.retain(|message| self.keep(message));
+    }
+}
+
+/// Inserts the messages replied to before their replies, unless they are in `messages`
+/// already. Each chain is followed up to `REPLY_CHAIN_MAX_DEPTH` messages, 5 by default,
+/// and ends at a message which can't be read, e.g. a deleted one.
+#[instrument(skip(client, messages), err)]
+pub async fn resolve_reply_chains(
+    client: &reqwest::Client,
+    messages: Vec<Message>,
+) -> Result<Vec<Message>, Error> {
+    let max_depth = match env::var("REPLY_CHAIN_MAX_DEPTH") {
+        Ok(depth) => depth.parse()?,
+        Err(_) => DEFAULT_REPLY_CHAIN_MAX_DEPTH,
+    };
+    let mut seen: HashSet<String> = messages.iter().map(|m| m.id.clone()).collect();
+    let mut results = Vec::new();
+    for message in messages {
+        let mut chain: Vec<Message> = Vec::new();
+        let mut replied = message.referenced_message.as_deref().cloned();
+        let mut next_reference = message.message_reference.clone();
+        while chain.len() < max_depth as usize {
+            let Some(reference) = next_reference.take().filter(|r| r.type_ == 0) else {
+                break;
+            };
+            let Some(message_id) = reference.message_id else {
+                break;
+            };
+            if seen.contains(&message_id) {
+                break;
+            }
+            let parent = match replied.take() {
+                Some(parent) => parent,
+                None => {
+                    let Some(channel_id) = reference.channel_id.or(message.channel_id.clone())
+                    else {
+                        break;
+                    };
+                    let response = get_get_message(client, &channel_id, &message_id).await?;
+                    if !response.status().is_success() {
+                        warn!(
+                            "failed to read replied message {message_id}: {}",
+                            response.status()
+                        );
+                        break;
+                    }
+                    response.json::<Message>().await?
+                }
+            };
+            seen.insert(parent.id.clone());
+            chain.push(parent);
+            next_reference = chain.last().and_then(|m| m.message_reference.clone());
+        }
+        results.extend(chain.into_iter().rev());
+        results.push(message);
+    }
+    Ok(results)
+}
+
+/// Converts channel messages in chronological order into conversation turns. Messages of
+/// the bot become assistant turns and the others user turns named after their author,
+/// with other bots labelled as such. Consecutive messages of the same author are merged.
+pub fn convert_messsages_to_chat_command_message(
+    messages: Vec<Message>,
+) -> Vec<ChatCommandMessage> {
+    let mut results = Vec::new();
+
+    let mut prev_author_id: Option<String> = None;
+    for msg in messages {
+        let content = msg
+            .get_text()
+            .map(|content| msg.resolve_mentions(&content))
+            .unwrap_or_default();
+        let same_author = prev_author_id.as_deref() == Some(msg.author.id.as_str());
+        prev_author_id = Some(msg.author.id.clone());
+        if msg.author.id == DISCORD_APPLICATION_ID.unwrap() {
+            match results.last_mut() {
+                Some(ChatCommandMessage::Assistant { content: prev }) if same_author => {
+                    prev.push('\n');
+                    prev.push_str(&content);
+                }
+                _ => results.push(ChatCommandMessage::assistant(content)),
+            }
+            continue;
+        }
+        match results.last_mut() {
+            Some(ChatCommandMessage::User {
+                content: prev,
+                attachments,
+                ..
+            }) if same_author => {
+                prev.append(content.into());
+                attachments.extend(msg.attachments);
+            }
+            _ => {
+                let name = if msg.is_other_bot(DISCORD_APPLICATION_ID.unwrap()) {
+                    format!("{} (bot)", msg.author_name())
+                } else {
+                    msg.author_name().to_string()
+                };
+                results.push(ChatCommandMessage::User {
+                    content: content.into(),
+                    name: Some(name),
+                    attachments: msg.attachments,
+                })
+            }
+        }
+    }
+
+    results
+}
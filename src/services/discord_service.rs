@@ -1,9 +1,13 @@
-use reqwest::Response;
+use reqwest::{
+    multipart::{Form, Part},
+    Response,
+};
 use serde::Serialize;
 use serde_json::json;
 use tracing::{info, instrument};
 
 use crate::{
+    constants::IMAGE_SIZES,
     endpoint::{
         application_command_item_endpoint, application_commands_endpoint, channel_item_endpoint,
         get_channel_message_item_endpoint, get_channel_messages_endpoint, get_followup_endpoint,
@@ -12,15 +16,73 @@ use crate::{
     },
     environment::DISCORD_BOT_TOKEN,
     error::Error,
-    models::application_command::{ApplicationCommand, ApplicationCommandOption},
+    models::application_command::{
+        ApplicationCommand, ApplicationCommandOption, ApplicationCommandOptionChoice,
+    },
 };
 
+/// Answers with a saved persona instead of the one of the channel
+fn generate_persona_option() -> ApplicationCommandOption {
+    ApplicationCommandOption {
+        name: "persona".to_string(),
+        type_: 3,
+        description: "Persona to answer as".to_string(),
+        required: Some(false),
+        min_length: Some(1),
+        max_value: None,
+        choices: None,
+        options: None,
+    }
+}
+
+/// Options of the generation settings, named as in `GenerationParams::KEYS`
+fn generate_params_options() -> Vec<ApplicationCommandOption> {
+    let option = |name: &str, type_: u32, description: &str| ApplicationCommandOption {
+        name: name.to_string(),
+        type_,
+        description: description.to_string(),
+        required: Some(false),
+        min_length: None,
+        max_value: None,
+        choices: None,
+        options: None,
+    };
+    vec![
+        option("model", 3, "Model to answer with"),
+        option("temperature", 10, "Sampling temperature from 0 to 2"),
+        option("top_p", 10, "Nucleus sampling probability from 0 to 1"),
+        option("max_tokens", 4, "Maximum tokens of the answer"),
+        option("stop", 3, "Up to 4 comma separated stop sequences"),
+        option("presence_penalty", 10, "Presence penalty from -2 to 2"),
+        option("frequency_penalty", 10, "Frequency penalty from -2 to 2"),
+        option("seed", 4, "Seed for reproducible answers"),
+        ApplicationCommandOption {
+            choices: Some(
+                ["text", "json_object"]
+                    .iter()
+                    .map(|format| ApplicationCommandOptionChoice {
+                        name: format.to_string(),
+                        value: format.to_string(),
+                    })
+                    .collect(),
+            ),
+            ..option("response_format", 3, "Format of the answer")
+        },
+    ]
+}
+
 pub fn generate_chat_command() -> ApplicationCommand {
     ApplicationCommand {
         name: "chat".to_string(),
         type_: 1,
         description: Some("ChatGPT command".to_string()),
-        options: None,
+        options: Some(
+            std::iter::once(generate_persona_option())
+                .chain(generate_params_options())
+                .collect(),
+        ),
+        integration_types: Some(vec![0]),
+        contexts: Some(vec![0, 1]),
     }
 }
 
@@ -29,14 +91,26 @@ pub fn generate_chats_command() -> ApplicationCommand {
         name: "chats".to_string(),
         type_: 1,
         description: Some("ChatGPT command".to_string()),
-        options: Some(vec![ApplicationCommandOption {
-            name: "n".to_string(),
-            type_: 4,
-            description: "Read messages count. default is 3".to_string(),
-            required: Some(false),
-            min_length: None,
-            max_value: Some(100),
-        }]),
+        options: Some(
+            vec![
+                ApplicationCommandOption {
+                    name: "n".to_string(),
+                    type_: 4,
+                    description: "Read messages count. default is 3".to_string(),
+                    required: Some(false),
+                    min_length: None,
+                    max_value: Some(100),
+                    choices: None,
+                    options: None,
+                },
+                generate_persona_option(),
+            ]
+            .into_iter()
+            .chain(generate_params_options())
+            .collect(),
+        ),
+        integration_types: Some(vec![0]),
+        contexts: Some(vec![0, 1]),
     }
 }
 
@@ -45,7 +119,202 @@ pub fn generate_chata_command() -> ApplicationCommand {
         name: "chata".to_string(),
         type_: 1,
         description: Some("All messages will be ingested. Only works in a thread".to_string()),
+        options: Some(
+            std::iter::once(generate_persona_option())
+                .chain(generate_params_options())
+                .collect(),
+        ),
+        integration_types: Some(vec![0]),
+        contexts: Some(vec![0, 1]),
+    }
+}
+
+pub fn generate_ask_command() -> ApplicationCommand {
+    ApplicationCommand {
+        name: "ask".to_string(),
+        type_: 1,
+        description: Some("Ask a question in a new thread".to_string()),
+        options: Some(
+            vec![
+                ApplicationCommandOption {
+                    name: "question".to_string(),
+                    type_: 3,
+                    description: "The question to ask".to_string(),
+                    required: Some(true),
+                    min_length: Some(1),
+                    max_value: None,
+                    choices: None,
+                    options: None,
+                },
+                generate_persona_option(),
+            ]
+            .into_iter()
+            .chain(generate_params_options())
+            .collect(),
+        ),
+        integration_types: Some(vec![0, 1]),
+        contexts: Some(vec![0, 1, 2]),
+    }
+}
+
+pub fn generate_prompt_command() -> ApplicationCommand {
+    ApplicationCommand {
+        name: "prompt".to_string(),
+        type_: 1,
+        description: Some("Enter a long prompt in a form".to_string()),
         options: None,
+        integration_types: Some(vec![0, 1]),
+        contexts: Some(vec![0, 1, 2]),
+    }
+}
+
+pub fn generate_imagine_command() -> ApplicationCommand {
+    ApplicationCommand {
+        name: "imagine".to_string(),
+        type_: 1,
+        description: Some("Generate an image".to_string()),
+        options: Some(vec![
+            ApplicationCommandOption {
+                name: "prompt".to_string(),
+                type_: 3,
+                description: "What the image shows".to_string(),
+                required: Some(true),
+                min_length: Some(1),
+                max_value: None,
+                choices: None,
+                options: None,
+            },
+            ApplicationCommandOption {
+                name: "size".to_string(),
+                type_: 3,
+                description: "Image size. default is 1024x1024".to_string(),
+                required: Some(false),
+                min_length: None,
+                max_value: None,
+                choices: Some(
+                    IMAGE_SIZES
+                        .iter()
+                        .map(|size| ApplicationCommandOptionChoice {
+                            name: size.to_string(),
+                            value: size.to_string(),
+                        })
+                        .collect(),
+                ),
+                options: None,
+            },
+        ]),
+        integration_types: Some(vec![0, 1]),
+        contexts: Some(vec![0, 1, 2]),
+    }
+}
+
+pub fn generate_persona_command() -> ApplicationCommand {
+    let sub_command = |name: &str, description: &str, options| ApplicationCommandOption {
+        name: name.to_string(),
+        type_: 1, // SUB_COMMAND
+        description: description.to_string(),
+        required: None,
+        min_length: None,
+        max_value: None,
+        choices: None,
+        options,
+    };
+    let string_option = |name: &str, description: &str, required: bool| ApplicationCommandOption {
+        name: name.to_string(),
+        type_: 3,
+        description: description.to_string(),
+        required: Some(required),
+        min_length: Some(1),
+        max_value: None,
+        choices: None,
+        options: None,
+    };
+    ApplicationCommand {
+        name: "persona".to_string(),
+        type_: 1,
+        description: Some("Manage the system prompts the bot answers with".to_string()),
+        options: Some(vec![
+            sub_command(
+                "set",
+                "Answer as a persona in this channel or thread, saving its prompt if given",
+                Some(vec![
+                    string_option("name", "Name of the persona", true),
+                    string_option("prompt", "System prompt to save under the name", false),
+                    ApplicationCommandOption {
+                        choices: Some(
+                            ["guild", "channel"]
+                                .iter()
+                                .map(|scope| ApplicationCommandOptionChoice {
+                                    name: scope.to_string(),
+                                    value: scope.to_string(),
+                                })
+                                .collect(),
+                        ),
+                        ..string_option(
+                            "scope",
+                            "Where the prompt is saved. default is guild",
+                            false,
+                        )
+                    },
+                ]),
+            ),
+            sub_command("show", "Show the persona answering here", None),
+            sub_command("clear", "Stop answering as a persona here", None),
+            sub_command("list", "List the personas available here", None),
+        ]),
+        integration_types: Some(vec![0, 1]),
+        contexts: Some(vec![0, 1, 2]),
+    }
+}
+
+pub fn generate_defaults_command() -> ApplicationCommand {
+    let sub_command = |name: &str, description: &str, options| ApplicationCommandOption {
+        name: name.to_string(),
+        type_: 1, // SUB_COMMAND
+        description: description.to_string(),
+        required: None,
+        min_length: None,
+        max_value: None,
+        choices: None,
+        options,
+    };
+    ApplicationCommand {
+        name: "defaults".to_string(),
+        type_: 1,
+        description: Some("Manage the generation settings of this server, or your own".to_string()),
+        options: Some(vec![
+            sub_command(
+                "set",
+                "Change the settings used when neither the command nor the topic sets them",
+                Some(generate_params_options()),
+            ),
+            sub_command("show", "Show the saved settings", None),
+            sub_command("clear", "Remove the saved settings", None),
+        ]),
+        integration_types: Some(vec![0, 1]),
+        contexts: Some(vec![0, 1, 2]),
+    }
+}
+
+pub fn generate_stop_command() -> ApplicationCommand {
+    ApplicationCommand {
+        name: "stop".to_string(),
+        type_: 1,
+        description: Some("Stop the answers being generated in this channel".to_string()),
+        options: None,
+        integration_types: Some(vec![0, 1]),
+        contexts: Some(vec![0, 1, 2]),
+    }
+}
+
+pub fn generate_topic_check_command() -> ApplicationCommand {
+    ApplicationCommand {
+        name: "topic-check".to_string(),
+        type_: 1,
+        description: Some("Show how the topic of this channel is understood".to_string()),
+        options: None,
+        integration_types: Some(vec![0, 1]),
+        contexts: Some(vec![0, 1, 2]),
     }
 }
 
@@ -55,6 +324,19 @@ pub fn generate_message_command() -> ApplicationCommand {
         type_: 3, // Message
         description: None,
         options: None,
+        integration_types: Some(vec![0, 1]),
+        contexts: Some(vec![0, 1, 2]),
+    }
+}
+
+pub fn generate_transcribe_command() -> ApplicationCommand {
+    ApplicationCommand {
+        name: "Transcribe".to_string(),
+        type_: 3, // Message
+        description: None,
+        options: None,
+        integration_types: Some(vec![0, 1]),
+        contexts: Some(vec![0, 1, 2]),
     }
 }
 
@@ -246,6 +528,15 @@ pub async fn get_get_channel(
     Ok(resp)
 }
 
+/// Where a page of channel messages is read from, by message id. Without a cursor the
+/// latest messages are read.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MessageCursor {
+    Before(String),
+    After(String),
+    Around(String),
+}
+
 /**
  * https://discord.com/developers/docs/resources/channel#get-channel-messages
  */
@@ -253,19 +544,26 @@ pub async fn get_get_channel(
 pub async fn get_get_messages(
     client: &reqwest::Client,
     channel_id: &str,
-    before: Option<String>,
+    cursor: Option<MessageCursor>,
     limit: Option<u32>,
 ) -> Result<Response, Error> {
     let limit = limit.or_else(|| Some(10)).unwrap();
-    let query_params = if let Some(before) = before {
-        json!({
+    let query_params = match cursor {
+        Some(MessageCursor::Before(before)) => json!({
             "before": before,
             "limit": limit,
-        })
-    } else {
-        json!({
+        }),
+        Some(MessageCursor::After(after)) => json!({
+            "after": after,
+            "limit": limit,
+        }),
+        Some(MessageCursor::Around(around)) => json!({
+            "around": around,
+            "limit": limit,
+        }),
+        None => json!({
             "limit": limit,
-        })
+        }),
     };
     let resp = client
         .get(get_channel_messages_endpoint(channel_id))
@@ -323,6 +621,29 @@ pub async fn post_message<T: Serialize + ?Sized>(
     Ok(resp)
 }
 
+/**
+ * https://discord.com/developers/docs/resources/channel#edit-message
+ */
+#[instrument(skip(client, payload), ret, err)]
+pub async fn edit_message<T: Serialize + ?Sized>(
+    client: &reqwest::Client,
+    channel_id: &str,
+    message_id: &str,
+    payload: &T,
+) -> Result<Response, Error> {
+    let resp = client
+        .patch(get_channel_message_item_endpoint(channel_id, message_id))
+        .header(
+            "Authorization",
+            format!("Bot {}", DISCORD_BOT_TOKEN.unwrap()),
+        )
+        .json(payload)
+        .send()
+        .await?;
+
+    Ok(resp)
+}
+
 /**
  * https://discord.com/developers/docs/interactions/receiving-and-responding#create-followup-message
  */
@@ -345,6 +666,56 @@ pub async fn post_followup_message<T: Serialize + ?Sized>(
     Ok(resp)
 }
 
+/**
+ * https://discord.com/developers/docs/reference#uploading-files
+ *
+ * Sends `payload` as `payload_json` with `files` as `files[n]`. `payload` should list
+ * the files in `attachments` with their index as `id`.
+ */
+#[instrument(skip(client, payload, files), ret, err)]
+pub async fn post_followup_message_with_files<T: Serialize + ?Sized>(
+    client: &reqwest::Client,
+    interaction_token: &str,
+    payload: &T,
+    files: &[UploadFile],
+) -> Result<Response, Error> {
+    let mut form = Form::new().part(
+        "payload_json",
+        Part::text(serde_json::to_string(payload)?).mime_str("application/json")?,
+    );
+    for (i, file) in files.iter().enumerate() {
+        form = form.part(format!("files[{i}]"), file.to_part()?);
+    }
+
+    let resp = client
+        .post(get_followup_endpoint(interaction_token))
+        .header(
+            "Authorization",
+            format!("Bot {}", DISCORD_BOT_TOKEN.unwrap()),
+        )
+        .multipart(form)
+        .send()
+        .await?;
+
+    Ok(resp)
+}
+
+/// A file uploaded with a message
+#[derive(Debug, Clone)]
+pub struct UploadFile {
+    pub filename: String,
+    pub content_type: String,
+    pub data: Vec<u8>,
+}
+
+impl UploadFile {
+    pub fn to_part(&self) -> Result<Part, Error> {
+        Ok(Part::bytes(self.data.clone())
+            .file_name(self.filename.clone())
+            .mime_str(&self.content_type)?)
+    }
+}
+
 /**
  * https://discord.com/developers/docs/interactions/receiving-and-responding#edit-followup-message
  */
@@ -368,6 +739,9 @@ pub async fn edit_followup_message<T: Serialize + ?Sized>(
     Ok(resp)
 }
 
+/**
+ * https://discord.com/developers/docs/resources/channel#start-thread-without-message
+ */
 #[instrument(skip(client), ret, err)]
 pub async fn post_start_thread(
     client: &reqwest::Client,
@@ -391,3 +765,15 @@ pub async fn post_start_thread(
 
     Ok(resp)
 }
+
+/**
+ * https://discord.com/developers/docs/reference#uploading-files
+ *
+ * Attachment urls are signed CDN urls, so no token is sent.
+ */
+#[instrument(skip(client), ret, err)]
+pub async fn get_attachment(client: &reqwest::Client, url: &str) -> Result<Response, Error> {
+    let resp = client.get(url).send().await?;
+
+    Ok(resp)
+}
@@ -0,0 +1,372 @@
+use aws_sdk_dynamodb::{
+    model::{AttributeValue, ReturnValue},
+    types::SdkError,
+};
+use chrono::{Duration, Utc};
+use tracing::instrument;
+
+use crate::{
+    constants::{COMMAND_CHANNEL_INDEX, COMMAND_THREAD_INDEX, RUNNING_COMMAND_MAX_SECONDS},
+    error::Error,
+    models::{
+        chatgpt::chat_completion::ChatCompletionUsage,
+        dynamo::{
+            defaults::Defaults,
+            discord_command::DiscordCommand,
+            persona::{ActivePersona, Persona},
+        },
+    },
+};
+
+#[instrument(skip(client, command), err)]
+pub async fn put_command(
+    client: &aws_sdk_dynamodb::Client,
+    table_name: &str,
+    command: &DiscordCommand,
+) -> Result<(), Error> {
+    client
+        .put_item()
+        .table_name(table_name)
+        .set_item(Some(serde_dynamo::to_item(command)?))
+        .send()
+        .await?;
+    Ok(())
+}
+
+#[instrument(skip(client), err)]
+pub async fn get_command(
+    client: &aws_sdk_dynamodb::Client,
+    table_name: &str,
+    id: &str,
+) -> Result<Option<DiscordCommand>, Error> {
+    let output = client
+        .get_item()
+        .table_name(table_name)
+        .key("Id", AttributeValue::S(id.to_string()))
+        .send()
+        .await?;
+    match output.item() {
+        Some(item) => Ok(Some(serde_dynamo::from_item(item.clone())?)),
+        None => Ok(None),
+    }
+}
+
+/// Flags the command as cancelled so an in-flight generation stops. Returns false when
+/// the command no longer exists, e.g. after it expired.
+#[instrument(skip(client), ret, err)]
+pub async fn set_command_cancelled(
+    client: &aws_sdk_dynamodb::Client,
+    table_name: &str,
+    id: &str,
+) -> Result<bool, Error> {
+    let result = client
+        .update_item()
+        .table_name(table_name)
+        .key("Id", AttributeValue::S(id.to_string()))
+        .condition_expression("attribute_exists(Id)")
+        .update_expression("SET Cancelled = :cancelled, UpdatedAt = :now")
+        .expression_attribute_values(":cancelled", AttributeValue::Bool(true))
+        .expression_attribute_values(
+            ":now",
+            AttributeValue::N(Utc::now().timestamp_millis().to_string()),
+        )
+        .send()
+        .await;
+    match result {
+        Ok(_) => Ok(true),
+        Err(SdkError::ServiceError(err)) if err.err().is_conditional_check_failed_exception() => {
+            Ok(false)
+        }
+        Err(err) => Err(err.into()),
+    }
+}
+
+#[instrument(skip(client), ret, err)]
+pub async fn is_command_cancelled(
+    client: &aws_sdk_dynamodb::Client,
+    table_name: &str,
+    id: &str,
+) -> Result<bool, Error> {
+    let output = client
+        .get_item()
+        .table_name(table_name)
+        .key("Id", AttributeValue::S(id.to_string()))
+        .projection_expression("Cancelled")
+        .consistent_read(true)
+        .send()
+        .await?;
+    let cancelled = output
+        .item()
+        .and_then(|item| item.get("Cancelled"))
+        .and_then(|value| value.as_bool().ok())
+        .copied()
+        .unwrap_or(false);
+    Ok(cancelled)
+}
+
+/// Records the token usage of the answer. Completed commands are never processed again.
+#[instrument(skip(client), err)]
+pub async fn set_command_completed(
+    client: &aws_sdk_dynamodb::Client,
+    table_name: &str,
+    id: &str,
+    usage: &ChatCompletionUsage,
+) -> Result<(), Error> {
+    let now = AttributeValue::N(Utc::now().timestamp_millis().to_string());
+    client
+        .update_item()
+        .table_name(table_name)
+        .key("Id", AttributeValue::S(id.to_string()))
+        .condition_expression("attribute_exists(Id)")
+        .update_expression("SET #usage = :usage, CompletedAt = :now, UpdatedAt = :now")
+        .expression_attribute_names("#usage", "Usage")
+        .expression_attribute_values(":usage", serde_dynamo::to_attribute_value(usage)?)
+        .expression_attribute_values(":now", now)
+        .send()
+        .await?;
+    Ok(())
+}
+
+/// Records the thread a `/ask` command is answered in, so it can be stopped from there
+#[instrument(skip(client), err)]
+pub async fn set_command_thread(
+    client: &aws_sdk_dynamodb::Client,
+    table_name: &str,
+    id: &str,
+    thread_id: &str,
+) -> Result<(), Error> {
+    client
+        .update_item()
+        .table_name(table_name)
+        .key("Id", AttributeValue::S(id.to_string()))
+        .condition_expression("attribute_exists(Id)")
+        .update_expression("SET ThreadId = :thread_id, UpdatedAt = :now")
+        .expression_attribute_values(":thread_id", AttributeValue::S(thread_id.to_string()))
+        .expression_attribute_values(
+            ":now",
+            AttributeValue::N(Utc::now().timestamp_millis().to_string()),
+        )
+        .send()
+        .await?;
+    Ok(())
+}
+
+/// Commands of `channel_id` which are still being answered. Answers of `/ask` belong to
+/// the channel the thread was started in and to the thread.
+#[instrument(skip(client), err)]
+pub async fn find_running_commands(
+    client: &aws_sdk_dynamodb::Client,
+    table_name: &str,
+    channel_id: &str,
+) -> Result<Vec<DiscordCommand>, Error> {
+    // Commands left behind by a failed worker are ignored after the Lambda timeout
+    let since = Utc::now() - Duration::seconds(RUNNING_COMMAND_MAX_SECONDS);
+    let mut commands = Vec::new();
+    for (index_name, key) in [
+        (COMMAND_CHANNEL_INDEX, "ChannelId"),
+        (COMMAND_THREAD_INDEX, "ThreadId"),
+    ] {
+        let mut start_key = None;
+        loop {
+            let output = client
+                .query()
+                .table_name(table_name)
+                .index_name(index_name)
+                .key_condition_expression("#key = :channel_id AND CreatedAt > :since")
+                .filter_expression(
+                    "attribute_not_exists(CompletedAt) \
+                     AND (attribute_not_exists(Cancelled) OR Cancelled = :false)",
+                )
+                .expression_attribute_names("#key", key)
+                .expression_attribute_values(
+                    ":channel_id",
+                    AttributeValue::S(channel_id.to_string()),
+                )
+                .expression_attribute_values(
+                    ":since",
+                    AttributeValue::N(since.timestamp_millis().to_string()),
+                )
+                .expression_attribute_values(":false", AttributeValue::Bool(false))
+                .set_exclusive_start_key(start_key)
+                .send()
+                .await?;
+            for item in output.items().unwrap_or_default() {
+                commands.push(serde_dynamo::from_item(item.clone())?);
+            }
+            start_key = output.last_evaluated_key().cloned();
+            if start_key.is_none() {
+                break;
+            }
+        }
+    }
+    Ok(commands)
+}
+
+#[instrument(skip(client, persona), err)]
+pub async fn put_persona(
+    client: &aws_sdk_dynamodb::Client,
+    table_name: &str,
+    persona: &Persona,
+) -> Result<(), Error> {
+    client
+        .put_item()
+        .table_name(table_name)
+        .set_item(Some(serde_dynamo::to_item(persona)?))
+        .send()
+        .await?;
+    Ok(())
+}
+
+#[instrument(skip(client), err)]
+pub async fn get_persona(
+    client: &aws_sdk_dynamodb::Client,
+    table_name: &str,
+    scope: &str,
+    name: &str,
+) -> Result<Option<Persona>, Error> {
+    let output = client
+        .get_item()
+        .table_name(table_name)
+        .key("Scope", AttributeValue::S(scope.to_string()))
+        .key("Key", AttributeValue::S(Persona::key(name)))
+        .send()
+        .await?;
+    match output.item() {
+        Some(item) => Ok(Some(serde_dynamo::from_item(item.clone())?)),
+        None => Ok(None),
+    }
+}
+
+/// Personas saved in `scope`, ordered by name
+#[instrument(skip(client), err)]
+pub async fn list_personas(
+    client: &aws_sdk_dynamodb::Client,
+    table_name: &str,
+    scope: &str,
+) -> Result<Vec<Persona>, Error> {
+    let mut personas = Vec::new();
+    let mut start_key = None;
+    loop {
+        let output = client
+            .query()
+            .table_name(table_name)
+            .key_condition_expression("Scope = :scope AND begins_with(#key, :prefix)")
+            .expression_attribute_names("#key", "Key")
+            .expression_attribute_values(":scope", AttributeValue::S(scope.to_string()))
+            .expression_attribute_values(":prefix", AttributeValue::S(Persona::key("")))
+            .set_exclusive_start_key(start_key)
+            .send()
+            .await?;
+        for item in output.items().unwrap_or_default() {
+            personas.push(serde_dynamo::from_item(item.clone())?);
+        }
+        start_key = output.last_evaluated_key().cloned();
+        if start_key.is_none() {
+            return Ok(personas);
+        }
+    }
+}
+
+#[instrument(skip(client), err)]
+pub async fn put_active_persona(
+    client: &aws_sdk_dynamodb::Client,
+    table_name: &str,
+    active_persona: &ActivePersona,
+) -> Result<(), Error> {
+    client
+        .put_item()
+        .table_name(table_name)
+        .set_item(Some(serde_dynamo::to_item(active_persona)?))
+        .send()
+        .await?;
+    Ok(())
+}
+
+#[instrument(skip(client), err)]
+pub async fn get_active_persona(
+    client: &aws_sdk_dynamodb::Client,
+    table_name: &str,
+    scope: &str,
+) -> Result<Option<ActivePersona>, Error> {
+    let output = client
+        .get_item()
+        .table_name(table_name)
+        .key("Scope", AttributeValue::S(scope.to_string()))
+        .key("Key", AttributeValue::S(ActivePersona::KEY.to_string()))
+        .send()
+        .await?;
+    match output.item() {
+        Some(item) => Ok(Some(serde_dynamo::from_item(item.clone())?)),
+        None => Ok(None),
+    }
+}
+
+/// Removes the persona selected in a channel or by a user. Returns whether it had one.
+#[instrument(skip(client), ret, err)]
+pub async fn delete_active_persona(
+    client: &aws_sdk_dynamodb::Client,
+    table_name: &str,
+    scope: &str,
+) -> Result<bool, Error> {
+    let output = client
+        .delete_item()
+        .table_name(table_name)
+        .key("Scope", AttributeValue::S(scope.to_string()))
+        .key("Key", AttributeValue::S(ActivePersona::KEY.to_string()))
+        .return_values(ReturnValue::AllOld)
+        .send()
+        .await?;
+    Ok(output.attributes().is_some())
+}
+
+#[instrument(skip(client, defaults), err)]
+pub async fn put_defaults(
+    client: &aws_sdk_dynamodb::Client,
+    table_name: &str,
+    defaults: &Defaults,
+) -> Result<(), Error> {
+    client
+        .put_item()
+        .table_name(table_name)
+        .set_item(Some(serde_dynamo::to_item(defaults)?))
+        .send()
+        .await?;
+    Ok(())
+}
+
+#[instrument(skip(client), err)]
+pub async fn get_defaults(
+    client: &aws_sdk_dynamodb::Client,
+    table_name: &str,
+    scope: &str,
+) -> Result<Option<Defaults>, Error> {
+    let output = client
+        .get_item()
+        .table_name(table_name)
+        .key("Scope", AttributeValue::S(scope.to_string()))
+        .key("Key", AttributeValue::S(Defaults::KEY.to_string()))
+        .send()
+        .await?;
+    match output.item() {
+        Some(item) => Ok(Some(serde_dynamo::from_item(item.clone())?)),
+        None => Ok(None),
+    }
+}
+
+/// Removes the defaults of a guild or user. Returns whether it had any.
+#[instrument(skip(client), ret, err)]
+pub async fn delete_defaults(
+    client: &aws_sdk_dynamodb::Client,
+    table_name: &str,
+    scope: &str,
+) -> Result<bool, Error> {
+    let output = client
+        .delete_item()
+        .table_name(table_name)
+        .key("Scope", AttributeValue::S(scope.to_string()))
+        .key("Key", AttributeValue::S(Defaults::KEY.to_string()))
+        .return_values(ReturnValue::AllOld)
+        .send()
+        .await?;
+    Ok(output.attributes().is_some())
+}
@@ -0,0 +1,46 @@
+use tracing::instrument;
+
+use crate::{
+    error::Error,
+    models::{
+        chatgpt::chat_completion::GenerationParams, discord::request::CommandInteractionOption,
+    },
+    services::dynamo_service::get_defaults,
+};
+
+/// Settings given as options of a command, named as in [`GenerationParams::KEYS`]
+pub fn params_from_options(
+    options: &[CommandInteractionOption],
+) -> Result<GenerationParams, String> {
+    let mut params = GenerationParams::default();
+    for option in options {
+        if let (true, Some(value)) = (
+            GenerationParams::KEYS.contains(&option.name.as_str()),
+            &option.value,
+        ) {
+            params.set(&option.name, &value.to_string())?;
+        }
+    }
+    Ok(params)
+}
+
+/// Settings of a command, in order of precedence: its options, the directives of the
+/// channel topic and the defaults saved in `scope`, of the guild or the user
+#[instrument(skip(client, topic, options), err)]
+pub async fn resolve_params(
+    client: &aws_sdk_dynamodb::Client,
+    table_name: Option<&str>,
+    scope: Option<&str>,
+    topic: &GenerationParams,
+    options: GenerationParams,
+) -> Result<GenerationParams, Error> {
+    let params = options.or(topic);
+    let defaults = match (table_name, scope) {
+        (Some(table_name), Some(scope)) => get_defaults(client, table_name, scope).await?,
+        _ => None,
+    };
+    Ok(match defaults {
+        Some(defaults) => params.or(&defaults.params),
+        None => params,
+    })
+}
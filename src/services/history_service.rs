@@ -0,0 +1,199 @@
+use std::env;
+
+use futures_util::{pin_mut, Stream, StreamExt};
+use tracing::instrument;
+
+use crate::{
+    constants::{DEFAULT_HISTORY_MAX_PAGES, DEFAULT_PROMPT_TOKEN_BUDGET, GET_MESSAGES_MAX_LIMIT},
+    error::Error,
+    models::{
+        chatgpt::chat_completion::estimate_tokens,
+        discord::{channel::Channel, message::Message},
+        dynamo::discord_command::HistoryRequest,
+    },
+    services::{
+        conversation_service::{resolve_reply_chains, HistoryFilter},
+        discord_service::{get_get_message, get_get_messages, MessageCursor},
+    },
+};
+
+/// How much of a channel is read as history, set by `HISTORY_MAX_PAGES` and
+/// `PROMPT_TOKEN_BUDGET`
+#[derive(Debug, Clone)]
+pub struct HistoryLimits {
+    /// Pages of 100 messages
+    pub max_pages: u32,
+    pub max_messages: Option<u32>,
+    /// Estimated tokens of the text of the messages
+    pub token_budget: u32,
+}
+
+impl HistoryLimits {
+    pub fn from_env() -> Result<Self, Error> {
+        let max_pages = match env::var("HISTORY_MAX_PAGES") {
+            Ok(pages) => pages.parse()?,
+            Err(_) => DEFAULT_HISTORY_MAX_PAGES,
+        };
+        let token_budget = match env::var("PROMPT_TOKEN_BUDGET") {
+            Ok(budget) => budget.parse()?,
+            Err(_) => DEFAULT_PROMPT_TOKEN_BUDGET,
+        };
+        Ok(Self {
+            max_pages,
+            max_messages: None,
+            token_budget,
+        })
+    }
+
+    pub fn with_max_messages(mut self, max_messages: Option<u32>) -> Self {
+        self.max_messages = max_messages;
+        self
+    }
+}
+
+/// Messages read page by page from `cursor`, at most `max_pages` pages. Messages are read
+/// from the newest without a cursor and with `Before`, and from the oldest with `After`.
+/// `Around` reads a single page, oldest first.
+pub fn history_stream<'a>(
+    client: &'a reqwest::Client,
+    channel_id: &'a str,
+    cursor: Option<MessageCursor>,
+    max_pages: u32,
+) -> impl Stream<Item = Result<Message, Error>> + 'a {
+    async_stream::try_stream! {
+        let mut cursor = cursor;
+        for _ in 0..max_pages {
+            let response =
+                get_get_messages(client, channel_id, cursor.clone(), Some(GET_MESSAGES_MAX_LIMIT))
+                    .await?;
+            if !response.status().is_success() {
+                Err(format!("failed to read messages: {}", response.status()))?;
+            }
+            let mut page = response.json::<Vec<Message>>().await?;
+            let is_last_page = (page.len() as u32) < GET_MESSAGES_MAX_LIMIT;
+            // Ids are snowflakes, increasing with time
+            page.sort_by_key(|m| (m.id.len(), m.id.clone()));
+            let next = match &cursor {
+                Some(MessageCursor::After(_)) => {
+                    page.last().map(|m| MessageCursor::After(m.id.clone()))
+                }
+                Some(MessageCursor::Around(_)) => None,
+                Some(MessageCursor::Before(_)) | None => {
+                    page.reverse();
+                    page.last().map(|m| MessageCursor::Before(m.id.clone()))
+                }
+            };
+            for message in page {
+                yield message;
+            }
+            match next {
+                Some(next) if !is_last_page => cursor = Some(next),
+                _ => break,
+            }
+        }
+    }
+}
+
+/// Reads the history of a channel in chronological order. Only messages kept by
+/// `filter` are counted, and reading stops before the one exceeding the token budget.
+#[instrument(skip(client, limits, filter), err)]
+pub async fn fetch_history(
+    client: &reqwest::Client,
+    channel_id: &str,
+    cursor: Option<MessageCursor>,
+    limits: &HistoryLimits,
+    filter: &HistoryFilter,
+) -> Result<Vec<Message>, Error> {
+    let newest_first = matches!(cursor, Some(MessageCursor::Before(_)) | None);
+    let stream = history_stream(client, channel_id, cursor, limits.max_pages);
+    pin_mut!(stream);
+    let mut messages = Vec::new();
+    let mut used_tokens = 0;
+    while let Some(message) = stream.next().await {
+        let message = message?;
+        if !filter.keep(&message) {
+            continue;
+        }
+        used_tokens += estimate_tokens(&message.get_text().unwrap_or_default());
+        if used_tokens > limits.token_budget {
+            break;
+        }
+        messages.push(message);
+        if Some(messages.len() as u32) == limits.max_messages {
+            break;
+        }
+    }
+    if newest_first {
+        messages.reverse();
+    }
+    Ok(messages)
+}
+
+/// Reads the history requested by a chat command, with the starter message of a thread
+/// and the messages replied to
+#[instrument(skip(client), err)]
+pub async fn read_requested_history(
+    client: &reqwest::Client,
+    channel_id: &str,
+    history: &HistoryRequest,
+) -> Result<Vec<Message>, Error> {
+    let filter = HistoryFilter::from_env()?;
+    let mut messages = fetch_history(
+        client,
+        channel_id,
+        Some(MessageCursor::Before(history.before.clone())),
+        &HistoryLimits::from_env()?.with_max_messages(history.max_messages),
+        &filter,
+    )
+    .await?;
+    let starter_channel_id = history.starter_channel_id.as_deref();
+    prepend_starter_message(client, channel_id, starter_channel_id, &mut messages).await?;
+    let mut messages = resolve_reply_chains(client, messages).await?;
+    filter.apply(&mut messages);
+    Ok(messages)
+}
+
+/// Puts the message a thread was started from in front of its history, when the history
+/// does not already reach it. `starter_channel_id` is the channel keeping the message.
+pub async fn prepend_starter_message(
+    client: &reqwest::Client,
+    thread_id: &str,
+    starter_channel_id: Option<&str>,
+    messages: &mut Vec<Message>,
+) -> Result<(), Error> {
+    if messages.first().is_some_and(|m| m.id == thread_id) {
+        return Ok(());
+    }
+    let Some(starter_channel_id) = starter_channel_id else {
+        return Ok(());
+    };
+    if let Some(message) = fetch_starter_message(client, thread_id, starter_channel_id).await? {
+        messages.insert(0, message);
+    }
+    Ok(())
+}
+
+/// The channel keeping the message a thread was started from. Forum posts keep it inside
+/// the post and other threads in their parent channel.
+pub fn starter_channel_id(thread: &Channel, is_forum_post: bool) -> Option<&str> {
+    if is_forum_post {
+        Some(&thread.id)
+    } else {
+        thread.parent_id.as_deref()
+    }
+}
+
+/// The message a thread was started from, which has the id of the thread. Threads started
+/// without a message, or whose message was deleted, have none.
+#[instrument(skip(client), err)]
+pub async fn fetch_starter_message(
+    client: &reqwest::Client,
+    thread_id: &str,
+    starter_channel_id: &str,
+) -> Result<Option<Message>, Error> {
+    let response = get_get_message(client, starter_channel_id, thread_id).await?;
+    if !response.status().is_success() {
+        return Ok(None);
+    }
+    Ok(Some(response.json::<Message>().await?))
+}
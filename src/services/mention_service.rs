@@ -0,0 +1,101 @@
+use std::env;
+
+use crate::{
+    error::Error,
+    models::discord::{allowed_mentions::AllowedMentions, webhook_request::WebhookRequest},
+};
+
+/// Resolves the mention types pinged in `guild_id` from a comma separated list of
+/// `guild_id=types` entries, where types are `users`, `roles` and `everyone` joined by
+/// `+`, e.g. `1234=users+roles`. An entry without `guild_id=` applies to the other
+/// guilds and to direct messages. Nothing is pinged by default.
+pub fn allowed_mentions_for_guild(config: Option<&str>, guild_id: Option<&str>) -> AllowedMentions {
+    let entries: Vec<(Option<&str>, &str)> = config
+        .unwrap_or_default()
+        .split(',')
+        .map(str::trim)
+        .filter(|entry| !entry.is_empty())
+        .map(|entry| match entry.split_once('=') {
+            Some((guild, types)) => (Some(guild.trim()), types),
+            None => (None, entry),
+        })
+        .collect();
+    let types = entries
+        .iter()
+        .find(|(guild, _)| guild.is_some() && *guild == guild_id)
+        .or_else(|| entries.iter().find(|(guild, _)| guild.is_none()))
+        .map(|(_, types)| *types)
+        .unwrap_or_default();
+    AllowedMentions::parse(
+        types
+            .split('+')
+            .map(str::trim)
+            .filter(|t| matches!(*t, "users" | "roles" | "everyone"))
+            .collect(),
+    )
+}
+
+/// Rewrites mention syntax into plain text, so it neither pings nor renders as a
+/// mention: `<@id>` becomes `@user`, `<@&id>` becomes `@role` and `@everyone`/`@here`
+/// are broken with a zero width space.
+pub fn rewrite_mentions(text: &str) -> String {
+    let mut result = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(i) = rest.find('@') {
+        let (before, tail) = (&rest[..i], &rest[i + 1..]);
+        if let Some(before) = before.strip_suffix('<') {
+            let (name, ids) = match tail.strip_prefix('&') {
+                Some(ids) => ("@role", ids),
+                None => ("@user", tail.strip_prefix('!').unwrap_or(tail)),
+            };
+            let digits = ids.len() - ids.trim_start_matches(|c: char| c.is_ascii_digit()).len();
+            if digits > 0 && ids[digits..].starts_with('>') {
+                result.push_str(before);
+                result.push_str(name);
+                rest = &ids[digits + 1..];
+                continue;
+            }
+        }
+        result.push_str(before);
+        result.push('@');
+        if tail.starts_with("everyone") || tail.starts_with("here") {
+            result.push('\u{200b}');
+        }
+        rest = tail;
+    }
+    result.push_str(rest);
+    result
+}
+
+/// Mentions of bot answers in a guild, set by `DISCORD_ALLOWED_MENTIONS` and
+/// `DISCORD_REWRITE_MENTIONS`
+#[derive(Debug, Clone)]
+pub struct MentionPolicy {
+    pub allowed_mentions: AllowedMentions,
+    pub rewrite: bool,
+}
+
+impl MentionPolicy {
+    pub fn from_env(guild_id: Option<&str>) -> Result<Self, Error> {
+        let config = env::var("DISCORD_ALLOWED_MENTIONS").ok();
+        let rewrite = match env::var("DISCORD_REWRITE_MENTIONS") {
+            Ok(rewrite) => rewrite.parse()?,
+            Err(_) => false,
+        };
+        Ok(Self {
+            allowed_mentions: allowed_mentions_for_guild(config.as_deref(), guild_id),
+            rewrite,
+        })
+    }
+
+    pub fn apply(&self, payload: WebhookRequest) -> WebhookRequest {
+        let mut payload = payload.with_allowed_mentions(self.allowed_mentions.clone());
+        if self.rewrite {
+            payload.content = rewrite_mentions(&payload.content);
+            for embed in payload.embeds.iter_mut().flatten() {
+                embed.description = embed.description.as_deref().map(rewrite_mentions);
+            }
+        }
+        payload
+    }
+}
@@ -1,2 +1,13 @@
+pub mod attachment_service;
 pub mod chatgpt_service;
+pub mod conversation_service;
 pub mod discord_service;
+pub mod dynamo_service;
+pub mod generation_service;
+pub mod history_service;
+pub mod mention_service;
+pub mod persona_service;
+pub mod reply_service;
+pub mod tool_service;
+pub mod topic_service;
+pub mod transcription_service;
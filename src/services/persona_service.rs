@@ -0,0 +1,190 @@
+use std::fmt;
+
+use tracing::instrument;
+
+use crate::{
+    error::Error,
+    models::{
+        discord::{channel::Channel, request::InteractionRequest},
+        dynamo::persona::{channel_scope, guild_scope, user_scope, Persona},
+    },
+    services::dynamo_service::{get_active_persona, get_persona},
+};
+
+/// Where the persona of an answer comes from, in order of precedence. Without any, the
+/// channel topic or the default prompt is used.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PersonaSource {
+    /// The `persona` option of the command
+    Option,
+    Thread,
+    Channel,
+    /// The selection of the user, where the app is installed to the user only
+    User,
+}
+
+impl fmt::Display for PersonaSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Option => write!(f, "command option"),
+            Self::Thread => write!(f, "thread"),
+            Self::Channel => write!(f, "channel"),
+            Self::User => write!(f, "user settings"),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ResolvedPersona {
+    pub persona: Persona,
+    pub source: PersonaSource,
+}
+
+/// Where personas and generation defaults are saved and selected
+#[derive(Debug, Clone)]
+pub enum PersonaPlace<'a> {
+    /// A channel or thread of a guild the app is installed to, or a DM with the bot
+    Channel(&'a Channel),
+    /// The settings of the user id, where the app is installed to the user only
+    User(String),
+}
+
+impl<'a> PersonaPlace<'a> {
+    /// Channels share their personas where the app is installed to the guild. Elsewhere,
+    /// including DMs, each user has their own.
+    pub fn of_interaction(
+        request: &InteractionRequest,
+        channel: &'a Channel,
+    ) -> Result<Self, Error> {
+        if request.is_guild_installed() {
+            return Ok(Self::Channel(channel));
+        }
+        let user = request.invoker().ok_or("user is required")?;
+        Ok(Self::User(user.id.clone()))
+    }
+
+    /// Scopes personas are looked up in, in order, with their labels
+    pub fn persona_scopes(&self) -> Vec<(String, &'static str)> {
+        match self {
+            Self::Channel(channel) => {
+                let mut scopes: Vec<(String, &str)> = persona_channels(channel)
+                    .into_iter()
+                    .map(|id| (channel_scope(id), "channel"))
+                    .collect();
+                if let Some(guild_id) = &channel.guild_id {
+                    scopes.push((guild_scope(guild_id), "guild"));
+                }
+                scopes
+            }
+            Self::User(user_id) => vec![(user_scope(user_id), "user")],
+        }
+    }
+
+    /// Scopes of the persona selections, in order of precedence
+    fn selection_scopes(&self) -> Vec<(String, PersonaSource)> {
+        match self {
+            Self::Channel(channel) => persona_channels(channel)
+                .into_iter()
+                .map(|id| {
+                    let source = if channel.is_thread() && id == channel.id {
+                        PersonaSource::Thread
+                    } else {
+                        PersonaSource::Channel
+                    };
+                    (channel_scope(id), source)
+                })
+                .collect(),
+            Self::User(user_id) => vec![(user_scope(user_id), PersonaSource::User)],
+        }
+    }
+
+    /// Scope `/persona set` and `/persona clear` change the selection of
+    pub fn selection_scope(&self) -> String {
+        match self {
+            Self::Channel(channel) => channel_scope(&channel.id),
+            Self::User(user_id) => user_scope(user_id),
+        }
+    }
+
+    /// Scope a persona is saved in, the guild rather than the channel when `shared`
+    pub fn save_scope(&self, shared: bool) -> String {
+        match self {
+            Self::Channel(Channel {
+                guild_id: Some(guild_id),
+                ..
+            }) if shared => guild_scope(guild_id),
+            _ => self.selection_scope(),
+        }
+    }
+
+    /// Scope of the generation defaults, if the place has any
+    pub fn defaults_scope(&self) -> Option<String> {
+        match self {
+            Self::Channel(channel) => channel.guild_id.as_deref().map(guild_scope),
+            Self::User(user_id) => Some(user_scope(user_id)),
+        }
+    }
+
+    /// Where selections apply, as in "Answering as ... in this thread"
+    pub fn describe(&self) -> &'static str {
+        match self {
+            Self::Channel(channel) if channel.is_thread() => "in this thread",
+            Self::Channel(_) => "in this channel",
+            Self::User(_) => "for you",
+        }
+    }
+}
+
+/// Channels whose personas apply to `channel`, the thread before its parent
+pub fn persona_channels(channel: &Channel) -> Vec<&str> {
+    match (channel.is_thread(), channel.parent_id.as_deref()) {
+        (true, Some(parent_id)) => vec![channel.id.as_str(), parent_id],
+        _ => vec![channel.id.as_str()],
+    }
+}
+
+/// Looks `name` up in the scopes of `place`: the channel, its parent and then the guild,
+/// or the user
+#[instrument(skip(client, place), err)]
+pub async fn find_persona(
+    client: &aws_sdk_dynamodb::Client,
+    table_name: &str,
+    place: &PersonaPlace<'_>,
+    name: &str,
+) -> Result<Option<Persona>, Error> {
+    for (scope, _) in place.persona_scopes().iter() {
+        if let Some(persona) = get_persona(client, table_name, scope, name).await? {
+            return Ok(Some(persona));
+        }
+    }
+    Ok(None)
+}
+
+/// The persona answering in `place`: the `requested` one, or the one selected in the
+/// thread or its channel, or by the user. A requested persona which doesn't exist
+/// resolves to `None`.
+#[instrument(skip(client, place), err)]
+pub async fn resolve_persona(
+    client: &aws_sdk_dynamodb::Client,
+    table_name: &str,
+    place: &PersonaPlace<'_>,
+    requested: Option<&str>,
+) -> Result<Option<ResolvedPersona>, Error> {
+    if let Some(name) = requested {
+        let persona = find_persona(client, table_name, place, name).await?;
+        return Ok(persona.map(|persona| ResolvedPersona {
+            persona,
+            source: PersonaSource::Option,
+        }));
+    }
+    for (scope, source) in place.selection_scopes() {
+        let Some(active) = get_active_persona(client, table_name, &scope).await? else {
+            continue;
+        };
+        // A selection whose persona can't be found falls through
+        if let Some(persona) = find_persona(client, table_name, place, &active.name).await? {
+            return Ok(Some(ResolvedPersona { persona, source }));
+        }
+    }
+    Ok(None)
+}
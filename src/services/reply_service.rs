@@ -0,0 +1,508 @@
+use std::{
+    env,
+    time::{Duration, Instant},
+};
+
+use futures_util::StreamExt;
+use reqwest::{Response, StatusCode};
+use tokio::time::sleep;
+use tracing::{error, info, instrument, warn};
+
+use crate::{
+    constants::{
+        DEFAULT_STREAM_EDIT_MIN_CHARS, DEFAULT_STREAM_EDIT_MIN_INTERVAL_MS,
+        EMBED_DESCRIPTION_MAX_LENGTH, EMBED_FIELD_VALUE_MAX_LENGTH, MESSAGE_CONTENT_MAX_LENGTH,
+    },
+    error::Error,
+    models::{
+        chatgpt::chat_completion::{
+            ChatCompletionMessage, ChatCompletionRequest, ChatCompletionToolCall,
+            ChatCompletionUsage,
+        },
+        discord::{
+            component::Component, embed::Embed, message::Message, webhook_request::WebhookRequest,
+        },
+    },
+    services::{
+        chatgpt_service::{post_chat_completions, response_extract_stream},
+        discord_service::{
+            edit_followup_message, edit_message, post_followup_message, post_message,
+        },
+        dynamo_service::{is_command_cancelled, set_command_completed},
+        mention_service::MentionPolicy,
+        tool_service::ToolSession,
+    },
+};
+
+/// Buttons attached to answers. Their `custom_id` is `{action}:{command id}`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AnswerButton {
+    Regenerate,
+    Continue,
+    Stop,
+}
+
+impl AnswerButton {
+    fn action(&self) -> &'static str {
+        match self {
+            Self::Regenerate => "regenerate",
+            Self::Continue => "continue",
+            Self::Stop => "stop",
+        }
+    }
+
+    pub fn custom_id(&self, command_id: &str) -> String {
+        format!("{}:{command_id}", self.action())
+    }
+
+    /// Splits a `custom_id` into the button and the command id
+    pub fn parse(custom_id: &str) -> Option<(Self, &str)> {
+        let (action, command_id) = custom_id.split_once(':')?;
+        let button = [Self::Regenerate, Self::Continue, Self::Stop]
+            .into_iter()
+            .find(|b| b.action() == action)?;
+        Some((button, command_id))
+    }
+
+    fn component(&self, command_id: &str) -> Component {
+        // https://discord.com/developers/docs/interactions/message-components#button-object-button-styles
+        let (style, label) = match self {
+            Self::Regenerate => (2, "Regenerate"),
+            Self::Continue => (2, "Continue"),
+            Self::Stop => (4, "Stop"),
+        };
+        Component::button(style, label.to_string(), self.custom_id(command_id))
+    }
+
+    /// Action row of `buttons` for the answer of `command_id`, labelled with the persona
+    /// that answered
+    pub fn action_row(buttons: &[Self], command_id: &str, persona: Option<&str>) -> Vec<Component> {
+        let mut components: Vec<Component> =
+            buttons.iter().map(|b| b.component(command_id)).collect();
+        if let Some(persona) = persona {
+            components.push(
+                Component::button(2, format!("Persona: {persona}"), "persona".to_string())
+                    .disabled(),
+            );
+        }
+        vec![Component::action_row(components)]
+    }
+}
+
+/// The command table entry an answer is generated for. Answers of a job get buttons and
+/// stop when the job is cancelled.
+#[derive(Debug, Clone, Copy)]
+pub struct AnswerJob<'a> {
+    pub command_id: &'a str,
+    pub dynamo_client: &'a aws_sdk_dynamodb::Client,
+    pub table_name: &'a str,
+    /// Tools the model may call while answering
+    pub tools: Option<ToolSession<'a>>,
+    /// Name of the persona answering
+    pub persona: Option<&'a str>,
+}
+
+/// Where a bot answer is written to
+#[derive(Debug, Clone, Copy)]
+pub enum ReplyTarget<'a> {
+    /// Followup messages of an interaction
+    Followup { interaction_token: &'a str },
+    /// Messages posted by the bot itself, e.g. in a thread
+    Channel { channel_id: &'a str },
+}
+
+impl ReplyTarget<'_> {
+    pub async fn post(
+        &self,
+        client: &reqwest::Client,
+        payload: &WebhookRequest,
+    ) -> Result<Response, Error> {
+        match self {
+            Self::Followup { interaction_token } => {
+                post_followup_message(client, interaction_token, payload).await
+            }
+            Self::Channel { channel_id } => post_message(client, channel_id, payload).await,
+        }
+    }
+
+    pub async fn edit(
+        &self,
+        client: &reqwest::Client,
+        message_id: &str,
+        payload: &WebhookRequest,
+    ) -> Result<Response, Error> {
+        match self {
+            Self::Followup { interaction_token } => {
+                edit_followup_message(client, message_id, interaction_token, payload).await
+            }
+            Self::Channel { channel_id } => {
+                edit_message(client, channel_id, message_id, payload).await
+            }
+        }
+    }
+
+    /// Edits `message`, or posts a new one until it exists
+    pub async fn send(
+        &self,
+        client: &reqwest::Client,
+        message: Option<&Message>,
+        payload: &WebhookRequest,
+    ) -> Result<Response, Error> {
+        match message {
+            Some(msg) => self.edit(client, &msg.id, payload).await,
+            None => self.post(client, payload).await,
+        }
+    }
+}
+
+/// How often a streamed answer is edited while it is generated
+#[derive(Debug, Clone, Copy)]
+pub struct StreamEditPolicy {
+    pub min_interval: Duration,
+    /// Characters generated since the last edit before editing again
+    pub min_chars: usize,
+}
+
+impl StreamEditPolicy {
+    pub fn from_env() -> Result<Self, Error> {
+        let min_interval_ms = match env::var("STREAM_EDIT_MIN_INTERVAL_MS") {
+            Ok(ms) => ms.parse()?,
+            Err(_) => DEFAULT_STREAM_EDIT_MIN_INTERVAL_MS,
+        };
+        let min_chars = match env::var("STREAM_EDIT_MIN_CHARS") {
+            Ok(chars) => chars.parse()?,
+            Err(_) => DEFAULT_STREAM_EDIT_MIN_CHARS,
+        };
+        Ok(Self {
+            min_interval: Duration::from_millis(min_interval_ms),
+            min_chars,
+        })
+    }
+}
+
+/// How answers are rendered, set by `REPLY_FORMAT`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReplyFormat {
+    Text,
+    /// The answer is the description of an embed, with the model, token usage and
+    /// latency in its footer
+    Embed,
+}
+
+impl ReplyFormat {
+    pub fn from_env() -> Result<Self, Error> {
+        match env::var("REPLY_FORMAT").as_deref() {
+            Ok("text") | Err(_) => Ok(Self::Text),
+            Ok("embed") => Ok(Self::Embed),
+            Ok(format) => Err(format!("unknown REPLY_FORMAT: {format}").into()),
+        }
+    }
+}
+
+/// Embed field value linking `links`, cut to the length Discord allows
+fn context_field_value(links: &[String]) -> String {
+    let mut value = String::new();
+    for (i, link) in links.iter().enumerate() {
+        let more = format!("+{} more", links.len() - i);
+        let item = format!("[{}]({link}) ", i + 1);
+        // Leave room for the count of the remaining links
+        if value.len() + item.len() + more.len() > EMBED_FIELD_VALUE_MAX_LENGTH {
+            value.push_str(&more);
+            break;
+        }
+        value.push_str(&item);
+    }
+    value.trim_end().to_string()
+}
+
+/// `text` followed by `suffix`, cut to `max_chars` characters with an ellipsis before
+/// the suffix, which is always kept
+fn fit_length(text: &str, suffix: &str, max_chars: usize) -> String {
+    let suffix_chars = suffix.chars().count();
+    if text.chars().count() + suffix_chars <= max_chars {
+        return format!("{text}{suffix}");
+    }
+    let kept = max_chars.saturating_sub(suffix_chars + 1);
+    text.chars()
+        .take(kept)
+        .chain(['…'])
+        .chain(suffix.chars())
+        .collect()
+}
+
+/**
+ * https://discord.com/developers/docs/topics/rate-limits#header-format
+ *
+ * Time to wait before the next request, when the bucket of `response` is exhausted or
+ * about to be.
+ */
+fn rate_limit_delay(response: &Response) -> Option<Duration> {
+    let header =
+        |name: &str| -> Option<f64> { response.headers().get(name)?.to_str().ok()?.parse().ok() };
+    if response.status() == StatusCode::TOO_MANY_REQUESTS {
+        return header("retry-after").map(Duration::from_secs_f64);
+    }
+    if header("x-ratelimit-remaining")? > 1.0 {
+        return None;
+    }
+    header("x-ratelimit-reset-after").map(Duration::from_secs_f64)
+}
+
+/// Requests a completion and streams it into a single message at `target`, starting with
+/// `prefix`. An error response of the completion API is posted as it is. `sources` are
+/// links to the messages used as context, shown with answers rendered as embeds.
+/// Mentions in the answer follow the `MentionPolicy` of `guild_id`.
+///
+/// Deltas are coalesced following `StreamEditPolicy::from_env`, and edits slow down when
+/// the rate limit bucket of the message runs low. The full answer is always sent last.
+///
+/// With a `job`, the message has a Stop button while streaming and Regenerate/Continue
+/// buttons afterwards. The job is checked for cancellation between chunks and the token
+/// usage is recorded on it at the end, estimated if the stream was stopped early.
+///
+/// When the job has tools, their calls are executed and the completion is requested
+/// again with the results, until the model answers or `max_steps` rounds are used.
+#[instrument(skip(client, request, job), err)]
+pub async fn stream_chat_completion(
+    client: &reqwest::Client,
+    target: ReplyTarget<'_>,
+    guild_id: Option<&str>,
+    request: &ChatCompletionRequest,
+    prefix: &str,
+    sources: &[String],
+    job: Option<AnswerJob<'_>>,
+) -> Result<Option<Message>, Error> {
+    let started_at = Instant::now();
+    let tools = job.and_then(|job| job.tools);
+    let mut request = request.clone();
+    if let Some(tools) = tools {
+        request.tools = Some(tools.registry.definitions());
+    }
+
+    let format = ReplyFormat::from_env()?;
+    let mentions = MentionPolicy::from_env(guild_id)?;
+    let payload =
+        |content: &str, suffix: &str, footer: Option<String>, buttons: &[AnswerButton]| {
+            let payload = match format {
+                ReplyFormat::Text => WebhookRequest::new(content),
+                ReplyFormat::Embed => {
+                    let mut embed = Embed::new(content);
+                    if let Some(footer) = footer {
+                        embed = embed.with_footer(footer);
+                        if !sources.is_empty() {
+                            embed = embed
+                                .with_field("Context".to_string(), context_field_value(sources));
+                        }
+                    }
+                    WebhookRequest::new("").with_embeds(vec![embed])
+                }
+            };
+            // Cut after mentions are rewritten, which lengthens the text
+            let mut payload = mentions.apply(payload);
+            match format {
+                ReplyFormat::Text => {
+                    payload.content =
+                        fit_length(&payload.content, suffix, MESSAGE_CONTENT_MAX_LENGTH)
+                }
+                ReplyFormat::Embed => {
+                    for embed in payload.embeds.iter_mut().flatten() {
+                        embed.description = embed
+                            .description
+                            .as_deref()
+                            .map(|d| fit_length(d, suffix, EMBED_DESCRIPTION_MAX_LENGTH));
+                    }
+                }
+            }
+            match job {
+                Some(job) => payload.with_components(AnswerButton::action_row(
+                    buttons,
+                    job.command_id,
+                    job.persona,
+                )),
+                None => payload,
+            }
+        };
+    let policy = StreamEditPolicy::from_env()?;
+    let mut buffer = prefix.to_string();
+    let mut sent_len = 0;
+    let mut next_edit_at = Instant::now();
+    let mut next_cancel_check_at = Instant::now() + policy.min_interval;
+    let mut rate_limited_until = Instant::now();
+    let mut usage = ChatCompletionUsage::default();
+    let mut estimated = false;
+    let mut stopped = false;
+    let mut message: Option<Message> = None;
+    let mut step = 0;
+    loop {
+        let response = post_chat_completions(client, &request).await?;
+        if !response.status().is_success() {
+            let err_text = response.text().await?;
+            error!("chatgpt error response: {err_text:?}");
+            target.post(client, &WebhookRequest::new(err_text)).await?;
+            // A failed tool call round still finishes the answer written so far
+            if message.is_some() {
+                break;
+            }
+            if let Some(job) = job {
+                set_command_completed(job.dynamo_client, job.table_name, job.command_id, &usage)
+                    .await?;
+            }
+            return Ok(None);
+        }
+
+        // Every read from the connection is yielded, edits are paced below
+        let mut stream = Box::pin(response_extract_stream(response, 0));
+        let step_start = buffer.len();
+        let mut step_usage: Option<ChatCompletionUsage> = None;
+        let mut tool_calls: Vec<ChatCompletionToolCall> = Vec::new();
+        while let Some(value) = stream.next().await {
+            let delta = value?;
+            if delta.usage.is_some() {
+                step_usage = delta.usage;
+            }
+            ChatCompletionToolCall::merge_deltas(&mut tool_calls, delta.tool_calls);
+            buffer.push_str(&delta.content);
+            // Checked on the edit interval, whether or not the edits go through
+            if let Some(job) = job.filter(|_| Instant::now() >= next_cancel_check_at) {
+                next_cancel_check_at = Instant::now() + policy.min_interval;
+                if is_command_cancelled(job.dynamo_client, job.table_name, job.command_id).await? {
+                    info!("command is cancelled: {}", job.command_id);
+                    stopped = true;
+                    break;
+                }
+            }
+            if buffer.len() == sent_len {
+                continue;
+            }
+            // The first part of the answer is posted right away
+            if Instant::now() < next_edit_at
+                || (message.is_some() && buffer[sent_len..].chars().count() < policy.min_chars)
+            {
+                continue;
+            }
+            let payload = payload(&buffer, "", None, &[AnswerButton::Stop]);
+            let response = target.send(client, message.as_ref(), &payload).await?;
+            let delay = rate_limit_delay(&response).unwrap_or_default();
+            rate_limited_until = Instant::now() + delay;
+            next_edit_at = Instant::now() + delay.max(policy.min_interval);
+            if !response.status().is_success() {
+                warn!("failed to update answer: {}", response.status());
+                continue;
+            }
+            sent_len = buffer.len();
+            if message.is_none() {
+                message = Some(response.json::<Message>().await?);
+            }
+        }
+        // Closes the connection, so the API stops generating a stopped answer
+        drop(stream);
+
+        match step_usage {
+            Some(step_usage) => usage.add(&step_usage),
+            None => {
+                estimated = true;
+                usage.add(&ChatCompletionUsage::estimate(
+                    &request,
+                    &buffer[step_start..],
+                ));
+            }
+        }
+        let Some(tools) = tools else {
+            break;
+        };
+        if stopped || tool_calls.is_empty() {
+            break;
+        }
+        step += 1;
+        request
+            .messages
+            .push(ChatCompletionMessage::assistant_tool_calls(
+                &buffer[step_start..],
+                tool_calls.clone(),
+            ));
+        for call in tool_calls.iter() {
+            info!(
+                "calling tool {} ({step}/{})",
+                call.function.name, tools.registry.max_steps
+            );
+            let result = tools.registry.call(client, tools.context, call).await;
+            request
+                .messages
+                .push(ChatCompletionMessage::tool(call.id.clone(), result));
+        }
+        // The model has to answer with the results it has
+        if step >= tools.registry.max_steps {
+            request.tool_choice = Some("none".to_string());
+        }
+        if let Some(job) = job {
+            if is_command_cancelled(job.dynamo_client, job.table_name, job.command_id).await? {
+                info!("command is cancelled: {}", job.command_id);
+                stopped = true;
+                break;
+            }
+        }
+    }
+
+    if let Some(job) = job {
+        set_command_completed(job.dynamo_client, job.table_name, job.command_id, &usage).await?;
+    }
+    let suffix = if stopped { " (stopped)" } else { "" };
+    if (buffer.is_empty() && !stopped)
+        || (job.is_none() && format == ReplyFormat::Text && buffer.len() == sent_len)
+    {
+        return Ok(message);
+    }
+    let footer = format!(
+        "{} · {}{} tokens · {:.1}s",
+        request.model,
+        if estimated { "~" } else { "" },
+        usage.total_tokens,
+        started_at.elapsed().as_secs_f64()
+    );
+    let payload = payload(
+        &buffer,
+        suffix,
+        Some(footer),
+        &[AnswerButton::Regenerate, AnswerButton::Continue],
+    );
+    sleep(rate_limited_until.saturating_duration_since(Instant::now())).await;
+    let mut response = target.send(client, message.as_ref(), &payload).await?;
+    if response.status() == StatusCode::TOO_MANY_REQUESTS {
+        sleep(rate_limit_delay(&response).unwrap_or(policy.min_interval)).await;
+        response = target.send(client, message.as_ref(), &payload).await?;
+    }
+    if !response.status().is_success() {
+        error!(
+            "failed to send the final answer: {}",
+            response.text().await?
+        );
+    } else if message.is_none() {
+        message = Some(response.json::<Message>().await?);
+    }
+    Ok(message)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keeps_text_within_the_limit() {
+        assert_eq!(fit_length("answer", "", 6), "answer");
+        assert_eq!(fit_length("answer", " (stopped)", 16), "answer (stopped)");
+    }
+
+    #[test]
+    fn cuts_text_by_characters() {
+        assert_eq!(fit_length("ääääää", "", 4), "äää…");
+        assert_eq!(fit_length("answer", "", 4).chars().count(), 4);
+    }
+
+    #[test]
+    fn keeps_the_suffix_when_cutting() {
+        let text = "a".repeat(MESSAGE_CONTENT_MAX_LENGTH);
+        let fitted = fit_length(&text, " (stopped)", MESSAGE_CONTENT_MAX_LENGTH);
+        assert_eq!(fitted.chars().count(), MESSAGE_CONTENT_MAX_LENGTH);
+        assert!(fitted.ends_with("a… (stopped)"));
+    }
+}
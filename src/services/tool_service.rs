@@ -0,0 +1,691 @@
+use std::{env, fmt::Debug};
+
+use futures_util::future::BoxFuture;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use tracing::{instrument, warn};
+
+use crate::{
+    constants::{DEFAULT_TOOL_MAX_STEPS, TOOL_HISTORY_MAX_MESSAGES},
+    error::Error,
+    models::{
+        chatgpt::chat_completion::{ChatCompletionTool, ChatCompletionToolCall},
+        discord::message::Message,
+    },
+    services::discord_service::{get_get_message, get_get_messages, MessageCursor},
+};
+
+/// Where the conversation of an answer takes place
+#[derive(Debug, Clone, Copy)]
+pub struct ToolContext<'a> {
+    pub channel_id: &'a str,
+    pub guild_id: Option<&'a str>,
+}
+
+/// A function the model can call. `definition` declares its JSON schema and `execute`
+/// gets the parsed arguments. The result is passed back to the model as it is.
+pub trait Tool: Debug + Send + Sync {
+    fn name(&self) -> &'static str;
+
+    fn definition(&self) -> ChatCompletionTool;
+
+    fn execute<'a>(
+        &'a self,
+        client: &'a reqwest::Client,
+        context: ToolContext<'a>,
+        arguments: Value,
+    ) -> BoxFuture<'a, Result<String, Error>>;
+}
+
+/// Tools offered to the model, with at most `max_steps` rounds of calls per answer
+#[derive(Debug)]
+pub struct ToolRegistry {
+    tools: Vec<Box<dyn Tool>>,
+    pub max_steps: u32,
+}
+
+impl ToolRegistry {
+    pub fn new(max_steps: u32) -> Self {
+        Self {
+            tools: Vec::new(),
+            max_steps,
+        }
+    }
+
+    /// Channel history, single messages and arithmetic
+    pub fn builtin(max_steps: u32) -> Self {
+        Self::new(max_steps)
+            .with_tool(ChannelHistoryTool)
+            .with_tool(MessageTool)
+            .with_tool(CalculatorTool)
+    }
+
+    /// The built-in tools with `TOOL_MAX_STEPS` rounds. Tools are off with 0.
+    pub fn from_env() -> Result<Self, Error> {
+        let max_steps = match env::var("TOOL_MAX_STEPS") {
+            Ok(steps) => steps.parse()?,
+            Err(_) => DEFAULT_TOOL_MAX_STEPS,
+        };
+        Ok(Self::builtin(max_steps))
+    }
+
+    pub fn with_tool<T: Tool + 'static>(mut self, tool: T) -> Self {
+        self.tools.push(Box::new(tool));
+        self
+    }
+
+    /// Tools for an answer in `context`, if any
+    pub fn session<'a>(&'a self, context: ToolContext<'a>) -> Option<ToolSession<'a>> {
+        (self.max_steps > 0 && !self.tools.is_empty()).then_some(ToolSession {
+            registry: self,
+            context,
+        })
+    }
+
+    pub fn definitions(&self) -> Vec<ChatCompletionTool> {
+        self.tools.iter().map(|tool| tool.definition()).collect()
+    }
+
+    /// Runs `call`. Errors are returned as the result, so the model can react to them.
+    #[instrument(skip(self, client, context), fields(name = %call.function.name))]
+    pub async fn call(
+        &self,
+        client: &reqwest::Client,
+        context: ToolContext<'_>,
+        call: &ChatCompletionToolCall,
+    ) -> String {
+        let name = call.function.name.as_str();
+        let Some(tool) = self.tools.iter().find(|tool| tool.name() == name) else {
+            return format!("error: unknown tool {name}");
+        };
+        let arguments = match call.function.arguments.trim() {
+            "" => json!({}),
+            arguments => match serde_json::from_str(arguments) {
+                Ok(arguments) => arguments,
+                Err(err) => return format!("error: invalid arguments: {err}"),
+            },
+        };
+        match tool.execute(client, context, arguments).await {
+            Ok(result) => result,
+            Err(err) => {
+                warn!("tool {name} failed: {err:?}");
+                format!("error: {err}")
+            }
+        }
+    }
+}
+
+/// The tools of a single answer
+#[derive(Debug, Clone, Copy)]
+pub struct ToolSession<'a> {
+    pub registry: &'a ToolRegistry,
+    pub context: ToolContext<'a>,
+}
+
+/// One line per message: id, time, author and text, with the message replied to
+fn format_message(message: &Message) -> String {
+    let mut line = format!(
+        "[{}] {} {}: {}",
+        message.id,
+        message.timestamp,
+        message.author.username,
+        message.get_text().unwrap_or_default()
+    );
+    for attachment in message.attachments.iter() {
+        if let Some(filename) = &attachment.filename {
+            line.push_str(&format!(" [attachment: {filename}]"));
+        }
+    }
+    if let Some(referenced) = &message.referenced_message {
+        line.push_str(&format!(" (reply to [{}])", referenced.id));
+    }
+    line
+}
+
+#[derive(Debug)]
+struct ChannelHistoryTool;
+
+#[derive(Debug, Deserialize)]
+struct ChannelHistoryArguments {
+    before: Option<String>,
+    limit: Option<u32>,
+}
+
+impl Tool for ChannelHistoryTool {
+    fn name(&self) -> &'static str {
+        "get_channel_history"
+    }
+
+    fn definition(&self) -> ChatCompletionTool {
+        ChatCompletionTool::function(
+            self.name(),
+            "Reads earlier messages of the current channel, oldest first.",
+            json!({
+                "type": "object",
+                "properties": {
+                    "before": {
+                        "type": "string",
+                        "description": "ID of the message to read before. The latest messages are read without it."
+                    },
+                    "limit": {
+                        "type": "integer",
+                        "minimum": 1,
+                        "maximum": TOOL_HISTORY_MAX_MESSAGES,
+                        "description": "Number of messages to read"
+                    }
+                }
+            }),
+        )
+    }
+
+    fn execute<'a>(
+        &'a self,
+        client: &'a reqwest::Client,
+        context: ToolContext<'a>,
+        arguments: Value,
+    ) -> BoxFuture<'a, Result<String, Error>> {
+        Box::pin(async move {
+            let arguments: ChannelHistoryArguments = serde_json::from_value(arguments)?;
+            let limit = arguments
+                .limit
+                .unwrap_or(20)
+                .clamp(1, TOOL_HISTORY_MAX_MESSAGES);
+            let response = get_get_messages(
+                client,
+                context.channel_id,
+                arguments.before.map(MessageCursor::Before),
+                Some(limit),
+            )
+            .await?;
+            if !response.status().is_success() {
+                return Err(format!("failed to read messages: {}", response.status()).into());
+            }
+            let messages = response.json::<Vec<Message>>().await?;
+            if messages.is_empty() {
+                return Ok("no messages".to_string());
+            }
+            let lines: Vec<String> = messages.iter().rev().map(format_message).collect();
+            Ok(lines.join("\n"))
+        })
+    }
+}
+
+#[derive(Debug)]
+struct MessageTool;
+
+#[derive(Debug, Deserialize)]
+struct MessageArguments {
+    message: String,
+}
+
+impl Tool for MessageTool {
+    fn name(&self) -> &'static str {
+        "get_message"
+    }
+
+    fn definition(&self) -> ChatCompletionTool {
+        ChatCompletionTool::function(
+            self.name(),
+            "Reads a single message, e.g. one that is replied to or linked.",
+            json!({
+                "type": "object",
+                "properties": {
+                    "message": {
+                        "type": "string",
+                        "description": "ID of a message in the current channel, or a message link"
+                    }
+                },
+                "required": ["message"]
+            }),
+        )
+    }
+
+    fn execute<'a>(
+        &'a self,
+        client: &'a reqwest::Client,
+        context: ToolContext<'a>,
+        arguments: Value,
+    ) -> BoxFuture<'a, Result<String, Error>> {
+        Box::pin(async move {
+            let arguments: MessageArguments = serde_json::from_value(arguments)?;
+            let message = arguments.message.trim();
+            // https://discord.com/channels/{guild_id or @me}/{channel_id}/{message_id}
+            let (channel_id, message_id) =
+                match message.strip_prefix("https://discord.com/channels/") {
+                    Some(path) => {
+                        let parts: Vec<&str> = path.split('/').collect();
+                        let [guild_id, channel_id, message_id] = parts[..] else {
+                            return Err("invalid message link".into());
+                        };
+                        // Messages of other servers are not shown, even if the bot can read them
+                        if guild_id != context.guild_id.unwrap_or("@me") {
+                            return Err("the message is outside this server".into());
+                        }
+                        (channel_id, message_id)
+                    }
+                    None => (context.channel_id, message),
+                };
+            let response = get_get_message(client, channel_id, message_id).await?;
+            if !response.status().is_success() {
+                return Err(format!("failed to read the message: {}", response.status()).into());
+            }
+            let message = response.json::<Message>().await?;
+            Ok(format_message(&message))
+        })
+    }
+}
+
+#[derive(Debug)]
+struct CalculatorTool;
+
+#[derive(Debug, Deserialize)]
+struct CalculatorArguments {
+    expression: String,
+}
+
+impl Tool for CalculatorTool {
+    fn name(&self) -> &'static str {
+        "calculate"
+    }
+
+    fn definition(&self) -> ChatCompletionTool {
+        ChatCompletionTool::function(
+            self.name(),
+            "Evaluates an arithmetic expression exactly. Supports decimals, + - * / % ^ and parentheses.",
+            json!({
+                "type": "object",
+                "properties": {
+                    "expression": {
+                        "type": "string",
+                        "description": "e.g. (1.5 + 2) * 3 / 7"
+                    }
+                },
+                "required": ["expression"]
+            }),
+        )
+    }
+
+    fn execute<'a>(
+        &'a self,
+        _client: &'a reqwest::Client,
+        _context: ToolContext<'a>,
+        arguments: Value,
+    ) -> BoxFuture<'a, Result<String, Error>> {
+        Box::pin(async move {
+            let arguments: CalculatorArguments = serde_json::from_value(arguments)?;
+            let value = evaluate(&arguments.expression)?;
+            Ok(value.to_string())
+        })
+    }
+}
+
+/// Fraction in lowest terms with a positive denominator
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Rational {
+    numerator: i128,
+    denominator: i128,
+}
+
+// On magnitudes, as `i128::MIN` has none as an `i128`
+fn gcd(a: u128, b: u128) -> u128 {
+    if b == 0 {
+        a
+    } else {
+        gcd(b, a % b)
+    }
+}
+
+const OVERFLOW: &str = "the number is too large";
+// Parentheses, signs and exponents nested deeper are rejected before the stack runs out
+const MAX_NESTING_DEPTH: usize = 64;
+
+impl Rational {
+    fn new(numerator: i128, denominator: i128) -> Result<Self, Error> {
+        if denominator == 0 {
+            return Err("division by zero".into());
+        }
+        let divisor = i128::try_from(gcd(numerator.unsigned_abs(), denominator.unsigned_abs()))
+            .ok()
+            .and_then(|divisor| divisor.checked_mul(denominator.signum()))
+            .ok_or(OVERFLOW)?;
+        Ok(Self {
+            numerator: numerator.checked_div(divisor).ok_or(OVERFLOW)?,
+            denominator: denominator.checked_div(divisor).ok_or(OVERFLOW)?,
+        })
+    }
+
+    fn integer(value: i128) -> Self {
+        Self {
+            numerator: value,
+            denominator: 1,
+        }
+    }
+
+    fn add(self, other: Self) -> Result<Self, Error> {
+        let numerator = self
+            .numerator
+            .checked_mul(other.denominator)
+            .zip(other.numerator.checked_mul(self.denominator))
+            .and_then(|(a, b)| a.checked_add(b))
+            .ok_or(OVERFLOW)?;
+        let denominator = self
+            .denominator
+            .checked_mul(other.denominator)
+            .ok_or(OVERFLOW)?;
+        Self::new(numerator, denominator)
+    }
+
+    fn neg(self) -> Result<Self, Error> {
+        Ok(Self {
+            numerator: self.numerator.checked_neg().ok_or(OVERFLOW)?,
+            ..self
+        })
+    }
+
+    fn mul(self, other: Self) -> Result<Self, Error> {
+        let numerator = self
+            .numerator
+            .checked_mul(other.numerator)
+            .ok_or(OVERFLOW)?;
+        let denominator = self
+            .denominator
+            .checked_mul(other.denominator)
+            .ok_or(OVERFLOW)?;
+        Self::new(numerator, denominator)
+    }
+
+    fn inverse(self) -> Result<Self, Error> {
+        Self::new(self.denominator, self.numerator)
+    }
+
+    /// Remainder of the division truncated toward zero
+    fn rem(self, other: Self) -> Result<Self, Error> {
+        let quotient = self.mul(other.inverse()?)?;
+        let truncated = Self::integer(quotient.numerator / quotient.denominator);
+        self.add(other.mul(truncated)?.neg()?)
+    }
+
+    fn pow(self, exponent: Self) -> Result<Self, Error> {
+        if exponent.denominator != 1 {
+            return Err("exponents must be integers".into());
+        }
+        let base = if exponent.numerator < 0 {
+            self.inverse()?
+        } else {
+            self
+        };
+        let exponent = u32::try_from(exponent.numerator.unsigned_abs()).map_err(|_| OVERFLOW)?;
+        let numerator = base.numerator.checked_pow(exponent).ok_or(OVERFLOW)?;
+        let denominator = base.denominator.checked_pow(exponent).ok_or(OVERFLOW)?;
+        Self::new(numerator, denominator)
+    }
+
+    /// Digits after the decimal point, when the value has a finite decimal expansion
+    fn decimal_places(&self) -> Option<u32> {
+        (0..=30).find(|&places| {
+            10i128
+                .checked_pow(places)
+                .is_some_and(|scale| scale % self.denominator == 0)
+        })
+    }
+}
+
+impl std::fmt::Display for Rational {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let Some(places) = self.decimal_places() else {
+            let approximate = self.numerator as f64 / self.denominator as f64;
+            return write!(
+                f,
+                "{}/{} (≈ {approximate})",
+                self.numerator, self.denominator
+            );
+        };
+        let scaled = 10i128
+            .pow(places)
+            .checked_div(self.denominator)
+            .and_then(|factor| self.numerator.checked_mul(factor));
+        let Some(scaled) = scaled else {
+            return write!(f, "{}/{}", self.numerator, self.denominator);
+        };
+        if places == 0 {
+            return write!(f, "{scaled}");
+        }
+        let digits = scaled.unsigned_abs().to_string();
+        let digits = format!("{digits:0>width$}", width = places as usize + 1);
+        let (integer, fraction) = digits.split_at(digits.len() - places as usize);
+        let sign = if scaled < 0 { "-" } else { "" };
+        write!(f, "{sign}{integer}.{fraction}")
+    }
+}
+
+/// Evaluates `expression` with the usual precedence. `^` is right associative.
+fn evaluate(expression: &str) -> Result<Rational, Error> {
+    let chars: Vec<char> = expression.chars().filter(|c| !c.is_whitespace()).collect();
+    let mut parser = Parser {
+        chars,
+        position: 0,
+        depth: 0,
+    };
+    let value = parser.expression()?;
+    if parser.position < parser.chars.len() {
+        return Err(format!("unexpected character: {}", parser.chars[parser.position]).into());
+    }
+    Ok(value)
+}
+
+struct Parser {
+    chars: Vec<char>,
+    position: usize,
+    depth: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.position).copied()
+    }
+
+    fn expression(&mut self) -> Result<Rational, Error> {
+        let mut value = self.term()?;
+        while let Some(operator @ ('+' | '-')) = self.peek() {
+            self.position += 1;
+            let term = self.term()?;
+            value = match operator {
+                '+' => value.add(term)?,
+                _ => value.add(term.neg()?)?,
+            };
+        }
+        Ok(value)
+    }
+
+    fn term(&mut self) -> Result<Rational, Error> {
+        let mut value = self.unary()?;
+        while let Some(operator @ ('*' | '/' | '%')) = self.peek() {
+            self.position += 1;
+            let factor = self.unary()?;
+            value = match operator {
+                '*' => value.mul(factor)?,
+                '/' => value.mul(factor.inverse()?)?,
+                _ => value.rem(factor)?,
+            };
+        }
+        Ok(value)
+    }
+
+    /// Signs apply after `^`, so `-2^2` is -4
+    fn unary(&mut self) -> Result<Rational, Error> {
+        self.depth += 1;
+        if self.depth > MAX_NESTING_DEPTH {
+            return Err("the expression is nested too deeply".into());
+        }
+        let value = match self.peek() {
+            Some('-') => {
+                self.position += 1;
+                self.unary().and_then(Rational::neg)
+            }
+            Some('+') => {
+                self.position += 1;
+                self.unary()
+            }
+            _ => self.power(),
+        };
+        self.depth -= 1;
+        value
+    }
+
+    fn power(&mut self) -> Result<Rational, Error> {
+        let base = self.primary()?;
+        if self.peek() == Some('^') {
+            self.position += 1;
+            let exponent = self.unary()?;
+            return base.pow(exponent);
+        }
+        Ok(base)
+    }
+
+    fn primary(&mut self) -> Result<Rational, Error> {
+        match self.peek() {
+            Some('(') => {
+                self.position += 1;
+                let value = self.expression()?;
+                if self.peek() != Some(')') {
+                    return Err("missing )".into());
+                }
+                self.position += 1;
+                Ok(value)
+            }
+            Some(c) if c.is_ascii_digit() || c == '.' => self.number(),
+            Some(c) => Err(format!("unexpected character: {c}").into()),
+            None => Err("unexpected end of the expression".into()),
+        }
+    }
+
+    fn number(&mut self) -> Result<Rational, Error> {
+        let mut numerator: i128 = 0;
+        let mut denominator: i128 = 1;
+        let mut fraction = false;
+        while let Some(c) = self.peek() {
+            match c {
+                '.' if !fraction => fraction = true,
+                '0'..='9' => {
+                    let digit = i128::from(c as u8 - b'0');
+                    numerator = numerator
+                        .checked_mul(10)
+                        .and_then(|n| n.checked_add(digit))
+                        .ok_or(OVERFLOW)?;
+                    if fraction {
+                        denominator = denominator.checked_mul(10).ok_or(OVERFLOW)?;
+                    }
+                }
+                _ => break,
+            }
+            self.position += 1;
+        }
+        Rational::new(numerator, denominator)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn calculate(expression: &str) -> Result<String, String> {
+        evaluate(expression)
+            .map(|value| value.to_string())
+            .map_err(|err| err.to_string())
+    }
+
+    #[test]
+    fn applies_precedence() {
+        assert_eq!(calculate("1 + 2 * 3"), Ok("7".to_string()));
+        assert_eq!(calculate("(1 + 2) * 3"), Ok("9".to_string()));
+        assert_eq!(calculate("10 - 4 - 3"), Ok("3".to_string()));
+        assert_eq!(calculate("2 * 3 ^ 2"), Ok("18".to_string()));
+    }
+
+    #[test]
+    fn raises_right_associatively() {
+        assert_eq!(calculate("2^3^2"), Ok("512".to_string()));
+        assert_eq!(calculate("(2^3)^2"), Ok("64".to_string()));
+        assert_eq!(calculate("2^-1"), Ok("0.5".to_string()));
+        assert_eq!(
+            calculate("2^0.5"),
+            Err("exponents must be integers".to_string())
+        );
+    }
+
+    #[test]
+    fn applies_signs_after_powers() {
+        assert_eq!(calculate("-2^2"), Ok("-4".to_string()));
+        assert_eq!(calculate("(-2)^2"), Ok("4".to_string()));
+        assert_eq!(calculate("--2"), Ok("2".to_string()));
+    }
+
+    #[test]
+    fn truncates_remainders_toward_zero() {
+        assert_eq!(calculate("7 % 3"), Ok("1".to_string()));
+        assert_eq!(calculate("-7 % 3"), Ok("-1".to_string()));
+        assert_eq!(calculate("7.5 % 2"), Ok("1.5".to_string()));
+    }
+
+    #[test]
+    fn formats_decimals_and_fractions() {
+        assert_eq!(calculate("0.1 + 0.2"), Ok("0.3".to_string()));
+        assert_eq!(calculate("-1 / 4"), Ok("-0.25".to_string()));
+        assert_eq!(calculate("1 / 8"), Ok("0.125".to_string()));
+        assert!(calculate("1 / 3").unwrap().starts_with("1/3 (≈ 0.333"));
+    }
+
+    #[test]
+    fn rejects_division_by_zero() {
+        assert_eq!(calculate("1 / 0"), Err("division by zero".to_string()));
+        assert_eq!(calculate("1 % 0"), Err("division by zero".to_string()));
+        assert_eq!(calculate("0^-1"), Err("division by zero".to_string()));
+    }
+
+    #[test]
+    fn rejects_overflow() {
+        assert_eq!(calculate("2^127"), Err(OVERFLOW.to_string()));
+        assert_eq!(calculate("10^40 * 10^40"), Err(OVERFLOW.to_string()));
+        assert_eq!(
+            calculate("100000000000000000000000000000000000000000"),
+            Err(OVERFLOW.to_string())
+        );
+        // (-2)^127 is i128::MIN, which has no positive counterpart
+        assert_eq!(calculate("(-2)^127"), Ok(i128::MIN.to_string()));
+        assert_eq!(calculate("1/(-2)^127"), Err(OVERFLOW.to_string()));
+        assert_eq!(calculate("-((-2)^127)"), Err(OVERFLOW.to_string()));
+    }
+
+    #[test]
+    fn rejects_malformed_expressions() {
+        assert_eq!(
+            calculate("1 +"),
+            Err("unexpected end of the expression".to_string())
+        );
+        assert_eq!(calculate("(1 + 2"), Err("missing )".to_string()));
+        assert_eq!(
+            calculate("1 + x"),
+            Err("unexpected character: x".to_string())
+        );
+        assert_eq!(
+            calculate("(1))"),
+            Err("unexpected character: )".to_string())
+        );
+    }
+
+    #[test]
+    fn limits_nesting_depth() {
+        let nested = |depth: usize| format!("{}1{}", "(".repeat(depth), ")".repeat(depth));
+        assert_eq!(
+            calculate(&nested(MAX_NESTING_DEPTH - 1)),
+            Ok("1".to_string())
+        );
+        assert_eq!(
+            calculate(&nested(MAX_NESTING_DEPTH)),
+            Err("the expression is nested too deeply".to_string())
+        );
+        assert!(calculate(&nested(100_000)).is_err());
+        assert!(calculate(&format!("{}1", "-".repeat(100_000))).is_err());
+        assert!(calculate(&format!("2{}", "^2".repeat(100_000))).is_err());
+    }
+}
@@ -0,0 +1,163 @@
+use tracing::instrument;
+
+use crate::{
+    constants::{DEFAULT_SYSTEM_PROMPT, HISTORY_MAX_MESSAGES},
+    error::Error,
+    models::{
+        chatgpt::chat_completion::GenerationParams,
+        discord::{channel::Channel, request::InteractionRequest},
+    },
+    services::discord_service::get_get_channel,
+};
+
+/// Settings given in the directive header of a topic
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct TopicDirectives {
+    pub params: GenerationParams,
+    /// Messages read as the history of the conversation
+    pub history: Option<u32>,
+}
+
+impl TopicDirectives {
+    fn set(&mut self, key: &str, value: &str) -> Result<(), String> {
+        match key.to_ascii_lowercase().as_str() {
+            "history" => match value.parse::<u32>() {
+                Ok(n) if (1..=HISTORY_MAX_MESSAGES).contains(&n) => self.history = Some(n),
+                _ => {
+                    return Err(format!(
+                        "history is a number from 1 to {HISTORY_MAX_MESSAGES}"
+                    ))
+                }
+            },
+            key => self.params.set(key, value)?,
+        }
+        Ok(())
+    }
+}
+
+/// A channel topic split into its directive header and the system prompt, e.g.
+///
+/// ```text
+/// model=gpt-4o temperature=0.2 history=20
+/// You answer in haiku
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct ChannelTopic {
+    /// Channel the topic was read from, the parent of a thread
+    pub channel_id: Option<String>,
+    pub prompt: Option<String>,
+    pub directives: TopicDirectives,
+    /// Directives which were ignored, with the reason
+    pub warnings: Vec<String>,
+    /// Title of a forum post, whose topic is the guidelines of the forum
+    pub post_title: Option<String>,
+    /// Names of the tags applied to a forum post
+    pub post_tags: Vec<String>,
+}
+
+impl ChannelTopic {
+    /// The leading lines made only of `key=value` pairs are directives and the rest of
+    /// the topic is the prompt. Invalid directives are ignored.
+    pub fn parse(topic: &str) -> Self {
+        let mut result = Self::default();
+        let mut lines = topic.trim_start().lines().peekable();
+        while let Some(line) = lines.next_if(|line| is_directive_line(line)) {
+            for pair in line.split_whitespace() {
+                let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+                if let Err(reason) = result.directives.set(key, value) {
+                    result.warnings.push(format!("`{pair}`: {reason}"));
+                }
+            }
+        }
+        let prompt = lines.collect::<Vec<_>>().join("\n");
+        let prompt = prompt.trim();
+        result.prompt = (!prompt.is_empty()).then(|| prompt.to_string());
+        result
+    }
+
+    pub fn is_forum_post(&self) -> bool {
+        self.post_title.is_some()
+    }
+
+    /// `prompt`, or the default one, followed by the title and tags of a forum post
+    pub fn with_post_context(&self, prompt: Option<String>) -> Option<String> {
+        let Some(title) = &self.post_title else {
+            return prompt;
+        };
+        let mut context = format!("This conversation is the forum post \"{title}\"");
+        if !self.post_tags.is_empty() {
+            context.push_str(&format!(", tagged {}", self.post_tags.join(", ")));
+        }
+        let prompt = prompt.unwrap_or(DEFAULT_SYSTEM_PROMPT.to_string());
+        Some(format!("{prompt}\n\n{context}"))
+    }
+}
+
+fn is_directive_line(line: &str) -> bool {
+    let mut pairs = line.split_whitespace().peekable();
+    pairs.peek().is_some()
+        && pairs.all(|pair| matches!(pair.split_once('='), Some((key, _)) if !key.is_empty()))
+}
+
+/// Topic of a channel. Threads use the topic of their parent channel, and forum posts the
+/// guidelines of their forum with their title and tags.
+#[instrument(skip(client, channel), fields(channel_id = %channel.id), err)]
+pub async fn get_channel_topic(
+    client: &reqwest::Client,
+    channel: &Channel,
+) -> Result<ChannelTopic, Error> {
+    let parent_channel = match (channel.is_thread(), &channel.parent_id) {
+        (true, Some(p_channel_id)) => Some(
+            get_get_channel(client, p_channel_id)
+                .await?
+                .json::<Channel>()
+                .await?,
+        ),
+        _ => None,
+    };
+    let topic_channel = match (channel.is_thread(), &parent_channel) {
+        (false, _) => Some(channel),
+        (true, parent_channel) => parent_channel.as_ref(),
+    };
+    let mut result = topic_channel
+        .and_then(|c| c.topic.as_deref())
+        .map(ChannelTopic::parse)
+        .unwrap_or_default();
+    result.channel_id = topic_channel.map(|c| c.id.clone());
+    if let Some(forum) = parent_channel.filter(|c| c.is_forum()) {
+        result.post_title = Some(channel.name.clone().unwrap_or_default());
+        result.post_tags = forum
+            .available_tags
+            .into_iter()
+            .filter(|tag| channel.applied_tags.contains(&tag.id))
+            .map(|tag| tag.name)
+            .collect();
+    }
+    Ok(result)
+}
+
+/// The channel of an interaction and its topic. Where the bot can't read the channel, the
+/// partial channel sent with the interaction is used, and threads go without the topic of
+/// their parent.
+#[instrument(skip(client, request), fields(channel_id = ?request.channel_id), err)]
+pub async fn get_interaction_channel(
+    client: &reqwest::Client,
+    request: &InteractionRequest,
+) -> Result<(Channel, ChannelTopic), Error> {
+    if !request.has_channel_access() {
+        let channel = request.channel.clone().ok_or("channel is required")?;
+        let topic = channel
+            .topic
+            .as_deref()
+            .map(ChannelTopic::parse)
+            .unwrap_or_default();
+        return Ok((channel, topic));
+    }
+    let channel_id = request.channel_id().ok_or("channel_id is required")?;
+    let channel = get_get_channel(client, channel_id)
+        .await?
+        .json::<Channel>()
+        .await?;
+    let topic = get_channel_topic(client, &channel).await?;
+    Ok((channel, topic))
+}
@@ -0,0 +1,128 @@
+use std::{env, fmt::Debug, sync::Arc};
+
+use futures_util::future::BoxFuture;
+use reqwest::multipart::Form;
+use serde::Deserialize;
+use tracing::instrument;
+
+use crate::{
+    constants::{AUDIO_ATTACHMENT_MAX_BYTES, CHATGPT_BASE_URL, DEFAULT_TRANSCRIPTION_MODEL},
+    endpoint::audio_transcriptions_endpoint,
+    environment::CHATGPT_API_KEY,
+    error::Error,
+    models::discord::attachment::Attachment,
+    services::discord_service::{get_attachment, UploadFile},
+};
+
+/// Speech-to-text backend
+pub trait Transcriber: Debug + Send + Sync {
+    fn transcribe<'a>(
+        &'a self,
+        client: &'a reqwest::Client,
+        audio: &'a UploadFile,
+    ) -> BoxFuture<'a, Result<String, Error>>;
+}
+
+/// The transcriptions API of OpenAI, or of a compatible server
+#[derive(Debug, Clone)]
+pub struct OpenAiTranscriber {
+    pub base_url: String,
+    pub api_key: Option<String>,
+    pub model: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct TranscriptionResponse {
+    text: String,
+}
+
+impl Transcriber for OpenAiTranscriber {
+    fn transcribe<'a>(
+        &'a self,
+        client: &'a reqwest::Client,
+        audio: &'a UploadFile,
+    ) -> BoxFuture<'a, Result<String, Error>> {
+        Box::pin(async move {
+            let form = Form::new()
+                .text("model", self.model.clone())
+                .text("response_format", "json")
+                .part("file", audio.to_part()?);
+            let mut builder = client
+                .post(audio_transcriptions_endpoint(&self.base_url))
+                .multipart(form);
+            if let Some(api_key) = &self.api_key {
+                builder = builder.header("Authorization", format!("Bearer {api_key}"));
+            }
+            let response = builder.send().await?;
+            if !response.status().is_success() {
+                return Err(format!("transcription failed: {}", response.text().await?).into());
+            }
+            let response = response.json::<TranscriptionResponse>().await?;
+            Ok(response.text.trim().to_string())
+        })
+    }
+}
+
+/// The backend set by `TRANSCRIPTION_PROVIDER`, `openai` by default or `none`. Set
+/// `TRANSCRIPTION_BASE_URL` to use a compatible server, which is sent
+/// `TRANSCRIPTION_API_KEY` if any, and `TRANSCRIPTION_MODEL` to change the model.
+pub fn transcriber_from_env() -> Result<Option<Arc<dyn Transcriber>>, Error> {
+    match env::var("TRANSCRIPTION_PROVIDER").as_deref() {
+        Ok("openai") | Err(_) => {}
+        Ok("none") => return Ok(None),
+        Ok(provider) => return Err(format!("unknown TRANSCRIPTION_PROVIDER: {provider}").into()),
+    }
+    // The OpenAI key is only sent to OpenAI
+    let (base_url, api_key) = match env::var("TRANSCRIPTION_BASE_URL") {
+        Ok(base_url) => (base_url, env::var("TRANSCRIPTION_API_KEY").ok()),
+        Err(_) => (
+            CHATGPT_BASE_URL.to_string(),
+            CHATGPT_API_KEY.map(str::to_string),
+        ),
+    };
+    let model = env::var("TRANSCRIPTION_MODEL").unwrap_or(DEFAULT_TRANSCRIPTION_MODEL.to_string());
+    Ok(Some(Arc::new(OpenAiTranscriber {
+        base_url,
+        api_key,
+        model,
+    })))
+}
+
+/// Attachments with an `audio/*` type, including voice messages
+pub fn is_audio_attachment(attachment: &Attachment) -> bool {
+    attachment.duration_secs.is_some()
+        || attachment
+            .content_type
+            .as_deref()
+            .is_some_and(|c| c.starts_with("audio/"))
+}
+
+/// Downloads an audio attachment and transcribes it
+#[instrument(skip(client, transcriber, attachment), fields(filename = ?attachment.filename), err)]
+pub async fn transcribe_attachment(
+    client: &reqwest::Client,
+    transcriber: &dyn Transcriber,
+    attachment: &Attachment,
+) -> Result<String, Error> {
+    if attachment.size.unwrap_or_default() > AUDIO_ATTACHMENT_MAX_BYTES {
+        return Err("audio is too large".into());
+    }
+    let url = attachment.url.as_deref().ok_or("attachment without url")?;
+    let response = get_attachment(client, url).await?;
+    if !response.status().is_success() {
+        return Err(format!("failed to download attachment: {}", response.status()).into());
+    }
+    let audio = UploadFile {
+        // Voice messages are `voice-message.ogg`
+        filename: attachment
+            .filename
+            .clone()
+            .unwrap_or("audio.ogg".to_string()),
+        content_type: attachment
+            .content_type
+            .clone()
+            .unwrap_or("audio/ogg".to_string()),
+        data: response.bytes().await?.to_vec(),
+    };
+    transcriber.transcribe(client, &audio).await
+}
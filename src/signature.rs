@@ -0,0 +1,376 @@
+use std::{
+    collections::HashMap,
+    env,
+    str::FromStr,
+    sync::{Arc, Mutex},
+    task::{Context, Poll},
+};
+
+use chrono::Utc;
+use ed25519_dalek::{PublicKey, Signature};
+use futures_util::future::{ready, Either, Ready};
+use lambda_http::http::{header::CONTENT_TYPE, HeaderValue, Request, Response, StatusCode};
+use lambda_runtime::tower::{Layer, Service};
+use serde::Deserialize;
+use tracing::{instrument, warn};
+
+use crate::{
+    constants::DEFAULT_SIGNATURE_MAX_SKEW_SECONDS, environment::DISCORD_BOT_PUBLIC_KEY,
+    error::Error,
+};
+
+#[derive(Deserialize)]
+struct InteractionId {
+    id: String,
+}
+
+/// Interaction ids seen within the last `ttl` seconds.
+#[derive(Debug)]
+struct ReplayCache {
+    ttl: i64,
+    seen: HashMap<String, i64>,
+}
+
+impl ReplayCache {
+    /// Returns false when `id` has already been seen and has not expired yet. Entries are
+    /// kept for at least `min_ttl` seconds.
+    fn insert(&mut self, id: String, now: i64, min_ttl: i64) -> bool {
+        self.seen.retain(|_, expires_at| *expires_at > now);
+        if self.seen.contains_key(&id) {
+            return false;
+        }
+        self.seen.insert(id, now + self.ttl.max(min_ttl));
+        true
+    }
+}
+
+/**
+ * https://discord.com/developers/docs/interactions/overview#setting-up-an-endpoint-validating-security-request-headers
+ *
+ * Public keys are parsed once, so a misconfigured key fails at startup instead of
+ * on every request. Several keys can be accepted at the same time during key rotation.
+ */
+#[derive(Debug)]
+pub struct SignatureValidator {
+    public_keys: Vec<PublicKey>,
+    max_timestamp_skew: i64,
+    replay_cache: Option<Mutex<ReplayCache>>,
+}
+
+impl SignatureValidator {
+    pub fn new(raw_public_keys: &[&str]) -> Result<Self, Error> {
+        let public_keys = raw_public_keys
+            .iter()
+            .map(|raw_public_key| {
+                PublicKey::from_bytes(&hex::decode(raw_public_key.trim())?)
+                    .map_err(|e| Error::from(format!("invalid public key: {e}")))
+            })
+            .collect::<Result<Vec<_>, Error>>()?;
+        if public_keys.is_empty() {
+            return Err("no public key is given".into());
+        }
+        Ok(Self {
+            public_keys,
+            max_timestamp_skew: DEFAULT_SIGNATURE_MAX_SKEW_SECONDS,
+            replay_cache: None,
+        })
+    }
+
+    /// Builds a validator from `DISCORD_BOT_PUBLIC_KEY`, a comma separated list of keys.
+    ///
+    /// `DISCORD_SIGNATURE_MAX_SKEW_SECONDS` overrides the accepted timestamp window and
+    /// `DISCORD_REPLAY_CACHE_SECONDS` enables the replay cache.
+    pub fn from_env() -> Result<Self, Error> {
+        let raw_public_key = DISCORD_BOT_PUBLIC_KEY.ok_or("DISCORD_BOT_PUBLIC_KEY is not set")?;
+        let raw_public_keys: Vec<&str> = raw_public_key.split(',').collect();
+        let mut validator = Self::new(&raw_public_keys)?;
+        if let Ok(skew) = env::var("DISCORD_SIGNATURE_MAX_SKEW_SECONDS") {
+            validator = validator.with_max_timestamp_skew(skew.parse()?);
+        }
+        if let Ok(ttl) = env::var("DISCORD_REPLAY_CACHE_SECONDS") {
+            validator = validator.with_replay_cache(ttl.parse()?);
+        }
+        Ok(validator)
+    }
+
+    pub fn with_max_timestamp_skew(mut self, seconds: i64) -> Self {
+        self.max_timestamp_skew = seconds;
+        self
+    }
+
+    /// Rejects interaction ids which were already accepted within `ttl` seconds, and at
+    /// least twice the timestamp skew, which is how long a replay passes the timestamp
+    /// check. The cache lives as long as the process, so it is best effort on Lambda.
+    pub fn with_replay_cache(mut self, ttl: i64) -> Self {
+        self.replay_cache = Some(Mutex::new(ReplayCache {
+            ttl,
+            seen: HashMap::new(),
+        }));
+        self
+    }
+
+    /// Verifies `signature` over `timestamp` + `body`. `now` is the current unix time in
+    /// seconds.
+    #[instrument(skip(self, body), err)]
+    pub fn validate(
+        &self,
+        signature: &str,
+        timestamp: &str,
+        body: &[u8],
+        now: i64,
+    ) -> Result<(), Error> {
+        let signed_at: i64 = timestamp
+            .parse()
+            .map_err(|_| format!("invalid signature timestamp: {timestamp}"))?;
+        if (now - signed_at).abs() > self.max_timestamp_skew {
+            return Err(
+                format!("signature timestamp is too old or in the future: {timestamp}").into(),
+            );
+        }
+
+        let signature = Signature::from_str(signature)?;
+        let mut msg = timestamp.as_bytes().to_vec();
+        msg.extend_from_slice(body);
+        if !self
+            .public_keys
+            .iter()
+            .any(|public_key| public_key.verify_strict(&msg, &signature).is_ok())
+        {
+            return Err("signature does not match any public key".into());
+        }
+
+        if let Some(cache) = &self.replay_cache {
+            let interaction: InteractionId = serde_json::from_slice(body)?;
+            let mut cache = cache.lock().map_err(|e| Error::from(e.to_string()))?;
+            // Both ends of the timestamp window are accepted
+            let min_ttl = 2 * self.max_timestamp_skew + 1;
+            if !cache.insert(interaction.id, now, min_ttl) {
+                return Err("interaction has already been received".into());
+            }
+        }
+        Ok(())
+    }
+
+    /// Validates the `X-Signature-*` headers of an HTTP request.
+    pub fn validate_request<B: AsRef<[u8]>>(&self, req: &Request<B>) -> Result<(), Error> {
+        let headers = req.headers();
+        let signature = headers
+            .get("X-Signature-Ed25519")
+            .ok_or("Header not found: X-Signature-Ed25519")?
+            .to_str()?;
+        let timestamp = headers
+            .get("X-Signature-Timestamp")
+            .ok_or("Header not found: X-Signature-Timestamp")?
+            .to_str()?;
+
+        self.validate(
+            signature,
+            timestamp,
+            req.body().as_ref(),
+            Utc::now().timestamp(),
+        )
+    }
+}
+
+/// Rejects requests without a valid Discord signature with `401` before they reach the
+/// inner service. Works with any `http` based service whose bodies are buffered, so it
+/// can wrap the Lambda handler as well as a standalone HTTP server.
+#[derive(Debug, Clone)]
+pub struct VerifySignatureLayer {
+    validator: Arc<SignatureValidator>,
+}
+
+impl VerifySignatureLayer {
+    pub fn new(validator: Arc<SignatureValidator>) -> Self {
+        Self { validator }
+    }
+}
+
+impl<S> Layer<S> for VerifySignatureLayer {
+    type Service = VerifySignature<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        VerifySignature {
+            inner,
+            validator: self.validator.clone(),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct VerifySignature<S> {
+    inner: S,
+    validator: Arc<SignatureValidator>,
+}
+
+impl<S, B, ResBody> Service<Request<B>> for VerifySignature<S>
+where
+    S: Service<Request<B>, Response = Response<ResBody>>,
+    B: AsRef<[u8]>,
+    ResBody: From<&'static str>,
+{
+    type Response = Response<ResBody>;
+    type Error = S::Error;
+    type Future = Either<Ready<Result<Response<ResBody>, S::Error>>, S::Future>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: Request<B>) -> Self::Future {
+        if let Err(err) = self.validator.validate_request(&req) {
+            warn!("invalid request signature: {err:?}");
+            let mut resp = Response::new(ResBody::from("invalid request signature"));
+            *resp.status_mut() = StatusCode::UNAUTHORIZED;
+            resp.headers_mut()
+                .insert(CONTENT_TYPE, HeaderValue::from_static("text/html"));
+            return Either::Left(ready(Ok(resp)));
+        }
+        Either::Right(self.inner.call(req))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ed25519_dalek::{Keypair, SecretKey, Signer};
+
+    use super::*;
+
+    const NOW: i64 = 1_700_000_000;
+    const BODY: &[u8] = br#"{"id":"1","type":1}"#;
+
+    /// Keypair derived from a fixed seed, so tests don't need a random source
+    fn keypair(seed: u8) -> Keypair {
+        let secret = SecretKey::from_bytes(&[seed; 32]).unwrap();
+        let public = PublicKey::from(&secret);
+        Keypair { secret, public }
+    }
+
+    fn public_key_hex(keypair: &Keypair) -> String {
+        hex::encode(keypair.public.as_bytes())
+    }
+
+    fn sign(keypair: &Keypair, timestamp: &str, body: &[u8]) -> String {
+        let mut msg = timestamp.as_bytes().to_vec();
+        msg.extend_from_slice(body);
+        hex::encode(keypair.sign(&msg).to_bytes())
+    }
+
+    fn validator(keypairs: &[&Keypair]) -> SignatureValidator {
+        let keys: Vec<String> = keypairs.iter().map(|k| public_key_hex(k)).collect();
+        let keys: Vec<&str> = keys.iter().map(String::as_str).collect();
+        SignatureValidator::new(&keys).unwrap()
+    }
+
+    #[test]
+    fn accepts_valid_signature() {
+        let keypair = keypair(1);
+        let timestamp = NOW.to_string();
+        let signature = sign(&keypair, &timestamp, BODY);
+        assert!(validator(&[&keypair])
+            .validate(&signature, &timestamp, BODY, NOW)
+            .is_ok());
+    }
+
+    #[test]
+    fn rejects_tampered_body() {
+        let keypair = keypair(1);
+        let timestamp = NOW.to_string();
+        let signature = sign(&keypair, &timestamp, BODY);
+        let tampered = br#"{"id":"1","type":2}"#;
+        assert!(validator(&[&keypair])
+            .validate(&signature, &timestamp, tampered, NOW)
+            .is_err());
+    }
+
+    #[test]
+    fn rejects_timestamps_beyond_skew() {
+        let keypair = keypair(1);
+        let validator = validator(&[&keypair]).with_max_timestamp_skew(60);
+        for signed_at in [NOW - 61, NOW + 61] {
+            let timestamp = signed_at.to_string();
+            let signature = sign(&keypair, &timestamp, BODY);
+            assert!(validator
+                .validate(&signature, &timestamp, BODY, NOW)
+                .is_err());
+        }
+        for signed_at in [NOW - 60, NOW + 60] {
+            let timestamp = signed_at.to_string();
+            let signature = sign(&keypair, &timestamp, BODY);
+            assert!(validator
+                .validate(&signature, &timestamp, BODY, NOW)
+                .is_ok());
+        }
+    }
+
+    #[test]
+    fn rejects_replay_until_cache_expires() {
+        let keypair = keypair(1);
+        let validator = validator(&[&keypair])
+            .with_max_timestamp_skew(30)
+            .with_replay_cache(90);
+        let timestamp = NOW.to_string();
+        let signature = sign(&keypair, &timestamp, BODY);
+        assert!(validator
+            .validate(&signature, &timestamp, BODY, NOW)
+            .is_ok());
+        assert!(validator
+            .validate(&signature, &timestamp, BODY, NOW + 30)
+            .is_err());
+        // Signed again after the entry expired
+        let timestamp = (NOW + 90).to_string();
+        let signature = sign(&keypair, &timestamp, BODY);
+        assert!(validator
+            .validate(&signature, &timestamp, BODY, NOW + 90)
+            .is_ok());
+    }
+
+    #[test]
+    fn keeps_replays_for_twice_the_skew() {
+        let keypair = keypair(1);
+        let validator = validator(&[&keypair])
+            .with_max_timestamp_skew(600)
+            .with_replay_cache(60);
+        // Signed at the end of the window, received at its start and replayed at its end
+        let timestamp = (NOW + 600).to_string();
+        let signature = sign(&keypair, &timestamp, BODY);
+        assert!(validator
+            .validate(&signature, &timestamp, BODY, NOW)
+            .is_ok());
+        for replayed_at in [NOW + 61, NOW + 600, NOW + 1200] {
+            assert!(validator
+                .validate(&signature, &timestamp, BODY, replayed_at)
+                .is_err());
+        }
+    }
+
+    #[test]
+    fn accepts_any_of_rotated_keys() {
+        let old_key = keypair(1);
+        let new_key = keypair(2);
+        let validator = validator(&[&old_key, &new_key]);
+        let timestamp = NOW.to_string();
+        for keypair in [&old_key, &new_key] {
+            let signature = sign(keypair, &timestamp, BODY);
+            assert!(validator
+                .validate(&signature, &timestamp, BODY, NOW)
+                .is_ok());
+        }
+        let signature = sign(&keypair(3), &timestamp, BODY);
+        assert!(validator
+            .validate(&signature, &timestamp, BODY, NOW)
+            .is_err());
+    }
+
+    #[test]
+    fn rejects_invalid_public_keys() {
+        let valid = public_key_hex(&keypair(1));
+        assert!(SignatureValidator::new(&["not hex"]).is_err());
+        // Valid hex, but not 32 bytes
+        assert!(SignatureValidator::new(&["abcd"]).is_err());
+        assert!(SignatureValidator::new(&[valid.as_str(), "zz"]).is_err());
+        assert!(SignatureValidator::new(&[]).is_err());
+        // Whitespace around the keys of `DISCORD_BOT_PUBLIC_KEY` is ignored
+        assert!(SignatureValidator::new(&[&format!(" {valid} ")]).is_ok());
+    }
+}